@@ -23,6 +23,11 @@ var currentMods = [];
 var modSize = 0;
 var pageCount = 0;
 
+function modName(mod) {
+    // Mods inside of a collection are shown along with it
+    return mod['collection'] ? `${mod['collection']} / ${mod['display_name']}` : mod['display_name'];
+}
+
 function createMod(mod_id) {
     var hidden = mods[mod_id]['is_disabled'] ? "hidden" : "";
     return `<button id="btn-mods-${mod_id}" data-mod-index="${mod_id}" tabindex="0" class="flex-button abstract-button All ${mods[mod_id]['category']}" nx-se-disabled="">
@@ -35,8 +40,8 @@ function createMod(mod_id) {
                 </div>
             </div>
             <div class="abstract-button-text f-u-bold mod-name"
-                style="display: block; font-size: 26px; text-indent: 10px; margin-top: 8px;" data-display_name="${mods[mod_id]['display_name']}">
-                <span class="marquee" data-msgid="textbox_id-4-1">${mods[mod_id]['display_name']}</span>
+                style="display: block; font-size: 26px; text-indent: 10px; margin-top: 8px;" data-display_name="${modName(mods[mod_id])}">
+                <span class="marquee" data-msgid="textbox_id-4-1">${modName(mods[mod_id])}</span>
             </div>
         </div>
     </div>
@@ -66,6 +71,42 @@ function toggleMod() {
     }));
 }
 
+function movePriority(offset) {
+    var index = parseInt($(".is-focused").attr("data-mod-index"));
+    if (isNaN(index)) { return; }
+
+    var rank = mods[index]["priority"];
+
+    // Swap places with the mod right above or below in the priority. Mod folders have their own priority, so a mod cannot go past the mods of another folder.
+    var other = mods.find(mod => mod["priority"] == rank + offset);
+    if (other == undefined || other["mod_folder"] != mods[index]["mod_folder"]) { return; }
+    other["priority"] = rank;
+    mods[index]["priority"] = rank + offset;
+
+    var order = mods.slice().sort((a, b) => a["priority"] - b["priority"]).map(mod => mod["id"]);
+    window.nx.sendMessage(JSON.stringify({
+        "SetPriority": {
+            "order": order
+        }
+    }));
+
+    if (document.getElementById('sortOptions').value == "priority") {
+        refreshCurrentMods();
+        var target = document.getElementById(`btn-mods-${index}`);
+        if (target != undefined) {
+            move(document.querySelector("#mods>button.is-focused"), target);
+        }
+    }
+}
+
+function raisePriority() {
+    movePriority(-1);
+}
+
+function lowerPriority() {
+    movePriority(1);
+}
+
 function updateCurrentDesc() {
     // Reset current description height
     currentDescHeight = 0;
@@ -365,6 +406,18 @@ function updateSort() {
             }
             return 0;
         });
+    } else if (sortType == "collection") {
+        currentMods = JSON.parse(JSON.stringify(currentMods)).sort((a, b) => {
+            var collectionA = `${mods[a]["mod_folder"]}/${mods[a]["collection"] || ""}`;
+            var collectionB = `${mods[b]["mod_folder"]}/${mods[b]["collection"] || ""}`;
+            if (collectionA < collectionB) { return -1; }
+            if (collectionA > collectionB) { return 1; }
+            if (mods[a]["display_name"] < mods[b]["display_name"]) { return -1; }
+            if (mods[a]["display_name"] > mods[b]["display_name"]) { return 1; }
+            return 0;
+        });
+    } else if (sortType == "priority") {
+        currentMods = JSON.parse(JSON.stringify(currentMods)).sort((a, b) => mods[a]["priority"] - mods[b]["priority"]);
     }
 
     if (descending) {
@@ -433,7 +486,16 @@ window.addEventListener("DOMContentLoaded", (e) => {
             }
         });
 
-        window.nx.footer.setAssign("X", "", () => {});
+        window.nx.footer.setAssign("X", "", () => {
+            if (currentState == MOD_MENU) {
+                raisePriority();
+            }
+        });
+        window.nx.footer.setAssign("ZR", "", () => {
+            if (currentState == MOD_MENU) {
+                lowerPriority();
+            }
+        });
         window.nx.footer.setAssign("B", "", () => {
             if (currentState == SUB_MENU) {
                 showModMenu();
@@ -1,17 +1,19 @@
 var selected_workspace = 0;
 
+function trackFocus(btn) {
+    btn.addEventListener("focus", () => {
+        btn.classList.add("is-focused");
+    });
+
+    btn.addEventListener("focusout", () => {
+        btn.classList.remove("is-focused");
+    });
+}
+
 window.addEventListener("DOMContentLoaded", (e) => {
     var buttons = document.querySelectorAll('button');
 
-    [].forEach.call(buttons, function(btn) {
-        btn.addEventListener("focus", () => {
-            btn.classList.add("is-focused");
-        });
-
-        btn.addEventListener("focusout", () => {
-            btn.classList.remove("is-focused");
-        });
-    });
+    [].forEach.call(buttons, trackFocus);
 
     window.addEventListener('keydown', function(e) {
         if (e.keyCode == UP) {
@@ -34,7 +36,11 @@ window.addEventListener("DOMContentLoaded", (e) => {
     }
 
     window.nx.addEventListener("message", function(e) {
-        document.getElementById(e.data).classList.toggle("hidden");
+        if (e.data.startsWith(MOD_FOLDERS_MESSAGE)) {
+            showModFolders(JSON.parse(e.data.substring(MOD_FOLDERS_MESSAGE.length)));
+        } else {
+            document.getElementById(e.data).classList.toggle("hidden");
+        }
     });
 
     window.nx.footer.setAssign("X", "", () => {});
@@ -43,7 +49,7 @@ window.addEventListener("DOMContentLoaded", (e) => {
     // Code to handle this session wasn't made to detect a closure by button
     window.nx.footer.setAssign("B", "", () => {
         if (getCurrentActiveContainer().attr("id") != "workspaces") {
-            changeDivFromTo('logging', 'workspaces', `0`);
+            changeDivFromTo(getCurrentActiveContainer().attr("id"), 'workspaces', selected_workspace);
         } else {
             submit(`exit`, `true`);
         }
@@ -57,6 +63,42 @@ function getCurrentActiveContainer() {
         return $("#workspaces");
     } else if ($("#logging").is(":visible")) {
         return $("#logging");
+    } else if ($("#mod_folders").is(":visible")) {
+        return $("#mod_folders");
+    }
+}
+
+// Sent by ARCropolis with the configured mod folders as JSON, from the highest priority to the lowest
+const MOD_FOLDERS_MESSAGE = "mod_folders:";
+
+function showModFolders(folders) {
+    $("#mod_folders .mod-folder").remove();
+
+    var container = $("#mod_folders .flex-container");
+
+    folders.forEach((folder) => {
+        var btn = $(`<button class="flex-item mod-folder">
+                <div class="icon-background"></div>
+                <div class="item-container">
+                    <h2></h2>
+                </div>
+            </button>`);
+        btn.find("h2").text(`${folder["path"]} (priority ${folder["priority"]})`);
+        btn.on("click", () => {
+            if (confirm(`Remove the mod folder ${folder["path"]}?`)) {
+                submit(`remove_mod_folder`, folder["path"]);
+            }
+        });
+        container.append(btn);
+        trackFocus(btn.get(0));
+    });
+}
+
+function addModFolder() {
+    var path = prompt("Path of the new mod folder, which gets the lowest priority", "sd:/ultimate/");
+
+    if (path != null && path.trim() != "") {
+        submit(`add_mod_folder`, path.trim());
     }
 }
 
@@ -0,0 +1,143 @@
+var options = [];
+var selected_option = 0;
+
+window.addEventListener("DOMContentLoaded", (e) => {
+    $.ajax({
+        dataType: "json",
+        url: "plugin_settings.json",
+        success: (data) => {
+            $("#title").text(data["title"]);
+            options = data["options"];
+            setupOptions();
+        }
+    });
+
+    window.addEventListener('keydown', function(e) {
+        if (e.keyCode == UP) {
+            var target = document.querySelector(".is-focused").previousElementSibling;
+            if (target != undefined) {
+                getCurrentActiveContainer()[0].scrollTop = target.offsetTop + 50;
+                target.focus();
+            }
+        } else if (e.keyCode == DOWN) {
+            var target = document.querySelector(".is-focused").nextElementSibling;
+            if (target != undefined) {
+                getCurrentActiveContainer()[0].scrollTop = target.offsetTop - 50;
+                target.focus();
+            }
+        }
+    });
+
+    window.nx.footer.setAssign("A", "", () => {
+        $(".is-focused").last().click();
+    });
+    window.nx.footer.setAssign("X", "", () => {});
+    window.nx.footer.setAssign("Y", "", () => {});
+
+    window.nx.footer.setAssign("B", "", () => {
+        if (getCurrentActiveContainer().attr("id") != "options") {
+            changeDivFromTo("choices", "options");
+        } else {
+            window.nx.sendMessage(JSON.stringify("ClosureRequest"));
+        }
+    });
+});
+
+function getCurrentActiveContainer() {
+    if ($("#options").is(":visible")) {
+        return $("#options");
+    } else if ($("#choices").is(":visible")) {
+        return $("#choices");
+    }
+}
+
+function makeButton(label, checked) {
+    var button = $(`<button class="flex-item">
+                    <div class="icon-background"><img class="abstract-icon is-appear" src="check.svg" /></div>
+                    <div class="item-container">
+                        <h2></h2>
+                    </div>
+                </button>`);
+
+    button.find("h2").text(label);
+    button.find("img").toggleClass("hidden", !checked);
+
+    button.on("focus", () => {
+        button.addClass("is-focused");
+    });
+
+    button.on("focusout", () => {
+        button.removeClass("is-focused");
+    });
+
+    return button;
+}
+
+function setupOptions() {
+    var list = $("#options-list");
+    list.empty();
+
+    options.forEach((option, index) => {
+        var button = makeButton(option["label"], option["kind"] == "flag" && option["value"] == "true");
+
+        if (option["kind"] == "flag") {
+            button.on("click", () => {
+                option["value"] = option["value"] == "true" ? "false" : "true";
+                button.find("img").toggleClass("hidden", option["value"] != "true");
+                submit(option["key"], option["value"]);
+            });
+        } else {
+            button.find("h2").text(`${option["label"]}: ${option["value"]}`);
+            button.on("click", () => {
+                selected_option = index;
+                setupChoices(option);
+                changeDivFromTo("options", "choices");
+            });
+        }
+
+        list.append(button);
+    });
+
+    if (list.find("button").length > 0) {
+        list.find("button").get(Math.min(selected_option, options.length - 1)).focus();
+    }
+}
+
+function setupChoices(option) {
+    var list = $("#choices-list");
+    list.empty();
+
+    option["choices"].forEach((choice) => {
+        var button = makeButton(choice, choice == option["value"]);
+
+        button.on("click", () => {
+            option["value"] = choice;
+            submit(option["key"], choice);
+            setupOptions();
+            changeDivFromTo("choices", "options");
+        });
+
+        list.append(button);
+    });
+}
+
+function changeDivFromTo(from, to) {
+    $(`#${from}`).fadeOut(200);
+    $(`#${from}`).promise().done(function() {
+        $(`#${to}`).fadeIn(200);
+        if (to == "options") {
+            $(`#${to}`).find("button").get(selected_option).focus();
+        } else {
+            $(`#${to}`).find("button").get(0).focus();
+        }
+    });
+}
+
+function submit(key, value) {
+    window.nx.sendMessage(JSON.stringify({
+        "Set": {
+            "key": key,
+            "value": value,
+        }
+    }));
+}
@@ -1,11 +1,19 @@
+pub mod arc;
 pub mod callback;
 pub mod event;
 pub mod file;
+pub mod mods;
+pub mod patch;
+pub mod settings;
 pub mod utils;
 
+pub use arc::*;
 pub use callback::*;
 pub use event::*;
 pub use file::*;
+pub use mods::*;
+pub use patch::*;
+pub use settings::*;
 pub use utils::*;
 
 #[repr(C)]
@@ -22,7 +30,29 @@ pub struct ApiVersion {
 /// Do your changes only add new APIs in a backwards compatible way: Minor bump
 ///
 /// Are your changes only internal? No version bump
-static API_VERSION: ApiVersion = ApiVersion { major: 1, minor: 8 };
+///
+/// List what every new minor version added below.
+///
+/// 1.9: `arcrop_register_patch_format`
+/// 1.10: `arcrop_register_extension_callback` runs the callbacks it registers
+/// 1.11: `arcrop_register_stream_data_callback`
+/// 1.12: `arcrop_register_event_callback_v2`
+/// 1.13: `arcrop_register_event_callback_with_mode`
+/// 1.14: `arcrop_get_enabled_mod_count`, `arcrop_get_enabled_mod_info`, `arcrop_get_mod_info`
+/// 1.15: `arcrop_get_file_source`
+/// 1.16: `arcrop_add_virtual_file`, `arcrop_add_virtual_file_with_path`
+/// 1.17: `arcrop_unregister_callback`, `arcrop_unregister_callback_with_path`, `arcrop_unregister_stream_data_callback`,
+///   `arcrop_replace_callback`, `arcrop_replace_callback_with_path`
+/// 1.18: `arcrop_register_callback_with_size`
+/// 1.19: `arcrop_arc_is_directory`, `arcrop_arc_list_search_folder`, `arcrop_arc_list_directory`, `arcrop_arc_get_shared_file`,
+///   `arcrop_arc_is_regional`, `arcrop_arc_load_vanilla_file`, `arcrop_arc_load_current_file`
+/// 1.20: `arcrop_register_settings_page`, `arcrop_get_setting_flag`, `arcrop_get_setting_value`
+static API_VERSION: ApiVersion = ApiVersion { major: 1, minor: 20 };
+
+/// Checks if a plugin built against the given API version can use this one
+pub fn is_api_version_supported(major: u32, minor: u32) -> bool {
+    major == API_VERSION.major && minor <= API_VERSION.minor
+}
 
 #[no_mangle]
 pub extern "C" fn arcrop_api_version() -> &'static ApiVersion {
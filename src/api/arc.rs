@@ -0,0 +1,222 @@
+use std::str::FromStr;
+
+use owo_colors::OwoColorize;
+use skyline::libc::c_char;
+use smash_arc::{ArcLookup, Hash40, Region, SearchLookup};
+
+use crate::{config, hashes, replacement::LoadedArcEx, resource};
+
+/// Writes as many hashes as fit in the buffer, along with the total amount so that plugins can call again with a bigger buffer.
+/// Returns false if some of them did not fit.
+fn write_hashes(found: &[Hash40], out_hashes: *mut Hash40, max_count: usize, out_count: &mut usize) -> bool {
+    *out_count = found.len();
+
+    let count = found.len().min(max_count);
+
+    if count != 0 {
+        let out_hashes = unsafe { std::slice::from_raw_parts_mut(out_hashes, count) };
+        out_hashes.copy_from_slice(&found[..count]);
+    }
+
+    found.len() <= max_count
+}
+
+/// Writes data into a plugin buffer, or only its size if it does not fit or there is no buffer
+fn write_data(data: &[u8], out_buffer: *mut u8, buf_length: usize, out_size: &mut usize) -> bool {
+    *out_size = data.len();
+
+    // Slices cannot be made out of a null pointer, even an empty one
+    if data.is_empty() {
+        return true;
+    }
+
+    if out_buffer.is_null() || data.len() > buf_length {
+        return false;
+    }
+
+    let buffer = unsafe { std::slice::from_raw_parts_mut(out_buffer, data.len()) };
+    buffer.copy_from_slice(data);
+
+    true
+}
+
+/// Gets the region to read a file in. A null region means the current one, and files without regional variants are always read as Region::None.
+fn file_region(hash: Hash40, region: *const c_char) -> Option<Region> {
+    let is_regional = resource::arc().get_file_info_from_hash(hash).ok()?.flags.is_regional();
+
+    if !is_regional {
+        Some(Region::None)
+    } else if region.is_null() {
+        Some(config::region())
+    } else {
+        Region::from_str(&unsafe { skyline::from_c_str(region) }).ok()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn arcrop_arc_is_directory(hash: Hash40) -> bool {
+    debug!("arcrop_arc_is_directory -> Received hash {} ({:#x})", hashes::find(hash).green(), hash.0);
+
+    resource::initialized()
+        && resource::search()
+            .get_path_list_entry_from_hash(hash)
+            .map_or(false, |entry| entry.is_directory())
+}
+
+/// Lists the path hashes of every file and folder directly inside a folder of the search section
+#[no_mangle]
+pub extern "C" fn arcrop_arc_list_search_folder(folder: Hash40, out_hashes: *mut Hash40, max_count: usize, out_count: &mut usize) -> bool {
+    debug!(
+        "arcrop_arc_list_search_folder -> Received hash {} ({:#x}), Max count: {}",
+        hashes::find(folder).green(),
+        folder.0,
+        max_count
+    );
+
+    *out_count = 0;
+
+    if !resource::initialized() {
+        return false;
+    }
+
+    let search = resource::search();
+    let mut children = Vec::new();
+    let mut child = search.get_first_child_in_folder(folder);
+
+    while let Ok(entry) = child {
+        children.push(entry.path.hash40());
+        child = search.get_next_child_in_folder(entry);
+    }
+
+    write_hashes(&children, out_hashes, max_count, out_count)
+}
+
+/// Lists the path hashes of the files that the game loads along with a directory of the data.arc, such as `fighter/mario/c00`
+#[no_mangle]
+pub extern "C" fn arcrop_arc_list_directory(directory: Hash40, out_hashes: *mut Hash40, max_count: usize, out_count: &mut usize) -> bool {
+    debug!(
+        "arcrop_arc_list_directory -> Received hash {} ({:#x}), Max count: {}",
+        hashes::find(directory).green(),
+        directory.0,
+        max_count
+    );
+
+    *out_count = 0;
+
+    if !resource::initialized() {
+        return false;
+    }
+
+    let arc = resource::arc();
+
+    let dir_info = match arc.get_dir_info_from_hash(directory) {
+        Ok(dir_info) => dir_info,
+        Err(_) => return false,
+    };
+
+    let file_paths = arc.get_file_paths();
+    let files: Vec<Hash40> = arc.get_file_infos()[dir_info.file_info_range()]
+        .iter()
+        .map(|info| file_paths[info.file_path_index].path.hash40())
+        .collect();
+
+    write_hashes(&files, out_hashes, max_count, out_count)
+}
+
+/// Follows the shared file chain of a file, and gets the path hash of the file whose data it actually uses
+#[no_mangle]
+pub extern "C" fn arcrop_arc_get_shared_file(hash: Hash40, out_hash: &mut Hash40) -> bool {
+    debug!(
+        "arcrop_arc_get_shared_file -> Received hash {} ({:#x})",
+        hashes::find(hash).green(),
+        hash.0
+    );
+
+    if !resource::initialized() {
+        return false;
+    }
+
+    let arc = resource::arc();
+
+    match arc.get_shared_file(hash) {
+        Ok(index) => {
+            *out_hash = arc.get_file_paths()[index].path.hash40();
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+/// Checks if a file has a variant for every region
+#[no_mangle]
+pub extern "C" fn arcrop_arc_is_regional(hash: Hash40) -> bool {
+    debug!("arcrop_arc_is_regional -> Received hash {} ({:#x})", hashes::find(hash).green(), hash.0);
+
+    resource::initialized()
+        && resource::arc()
+            .get_file_info_from_hash(hash)
+            .map_or(false, |info| info.flags.is_regional())
+}
+
+/// Reads the data.arc's data of a file in a region (`us_en`, `jp_ja`...), or the current region if it is null.
+/// If the buffer is too small, only the required size is written and false is returned.
+#[no_mangle]
+pub extern "C" fn arcrop_arc_load_vanilla_file(
+    hash: Hash40,
+    region: *const c_char,
+    out_buffer: *mut u8,
+    buf_length: usize,
+    out_size: &mut usize,
+) -> bool {
+    debug!(
+        "arcrop_arc_load_vanilla_file -> Received hash {} ({:#x}), Buffer len: {:#x}",
+        hashes::find(hash).green(),
+        hash.0,
+        buf_length
+    );
+
+    *out_size = 0;
+
+    if !resource::initialized() {
+        return false;
+    }
+
+    let region = match file_region(hash, region) {
+        Some(region) => region,
+        None => return false,
+    };
+
+    match resource::arc().get_file_contents(hash, region) {
+        Ok(data) => write_data(&data, out_buffer, buf_length, out_size),
+        Err(e) => {
+            debug!("arcrop_arc_load_vanilla_file -> Failed to read the file. Reason: {:?}", e);
+            false
+        },
+    }
+}
+
+/// Reads the data the game gets when loading a file: the modded data if a mod or plugin provides it, the data.arc's data otherwise.
+/// If the buffer is too small, only the required size is written and false is returned.
+#[no_mangle]
+pub extern "C" fn arcrop_arc_load_current_file(hash: Hash40, out_buffer: *mut u8, buf_length: usize, out_size: &mut usize) -> bool {
+    debug!(
+        "arcrop_arc_load_current_file -> Received hash {} ({:#x}), Buffer len: {:#x}",
+        hashes::find(hash).green(),
+        hash.0,
+        buf_length
+    );
+
+    // Same as arcrop_load_file, this can be called by callbacks while the filesystem is locked
+    let filesystem = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
+
+    if filesystem.local_hash(hash).is_some() {
+        *out_size = 0;
+
+        match filesystem.load(hash) {
+            Some(data) => write_data(&data, out_buffer, buf_length, out_size),
+            None => false,
+        }
+    } else {
+        arcrop_arc_load_vanilla_file(hash, std::ptr::null(), out_buffer, buf_length, out_size)
+    }
+}
@@ -1,14 +1,43 @@
+use std::{collections::HashMap, sync::Arc};
+
 use arcropolis_api::{CallbackFn, StreamCallbackFn};
 use once_cell::sync::Lazy;
 use owo_colors::OwoColorize;
-use parking_lot::Mutex;
-use smash_arc::Hash40;
+use parking_lot::{Mutex, RwLock};
+use skyline::libc::c_char;
+use smash_arc::{ArcLookup, Hash40};
 
 use crate::{fs::*, hashes};
 
 pub enum PendingApiCall {
     GenericCallback { hash: Hash40, max_size: usize, callback: CallbackFn },
     StreamCallback { hash: Hash40, callback: StreamCallbackFn },
+    StreamDataCallback { hash: Hash40, size: usize, callback: StreamDataCallbackFn },
+    VirtualFile { hash: Hash40, data: Arc<[u8]> },
+    SizedCallback { hash: Hash40, size_callback: SizeCallbackFn, callback: CallbackFn },
+}
+
+impl PendingApiCall {
+    fn hash(&self) -> Hash40 {
+        match self {
+            Self::GenericCallback { hash, .. }
+            | Self::StreamCallback { hash, .. }
+            | Self::StreamDataCallback { hash, .. }
+            | Self::VirtualFile { hash, .. }
+            | Self::SizedCallback { hash, .. } => *hash,
+        }
+    }
+
+    /// The address of the plugin function of this call, if it has one
+    fn callback_address(&self) -> Option<usize> {
+        match self {
+            Self::GenericCallback { callback, .. } => Some(*callback as usize),
+            Self::StreamCallback { callback, .. } => Some(*callback as usize),
+            Self::StreamDataCallback { callback, .. } => Some(*callback as usize),
+            Self::SizedCallback { callback, .. } => Some(*callback as usize),
+            Self::VirtualFile { .. } => None,
+        }
+    }
 }
 
 unsafe impl Send for PendingApiCall {}
@@ -16,6 +45,21 @@ unsafe impl Sync for PendingApiCall {}
 
 pub static PENDING_CALLBACKS: Lazy<Mutex<Vec<PendingApiCall>>> = Lazy::new(|| Mutex::new(Vec::new()));
 
+/// Called with the hash of the stream file, the buffer to fill and its size, and the offset to read from.
+/// The amount of bytes written has to be provided, and true returned if the read succeeded.
+pub type StreamDataCallbackFn = extern "C" fn(u64, *mut u8, usize, usize, &mut usize) -> bool;
+
+/// Called with the hash of the file right before the game allocates its buffer.
+/// The exact size of the file has to be written, and true returned if it could be computed.
+pub type SizeCallbackFn = extern "C" fn(u64, &mut usize) -> bool;
+
+/// Called with the hash of the file, its data, the size of the buffer holding it and the current size of the data.
+/// The data can be edited in place, in which case the new size has to be written and true returned.
+pub type ExtensionCallbackFn = extern "C" fn(u64, *mut u8, usize, &mut usize) -> bool;
+
+// Every callback registered for a file extension, in registration order
+static EXTENSION_CALLBACKS: Lazy<RwLock<HashMap<Hash40, Vec<ExtensionCallbackFn>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
 #[no_mangle]
 pub extern "C" fn arcrop_register_callback(hash: Hash40, max_size: usize, cb: CallbackFn) {
     debug!(
@@ -39,6 +83,31 @@ pub extern "C" fn arcrop_register_callback(hash: Hash40, max_size: usize, cb: Ca
     }
 }
 
+/// Same as `arcrop_register_callback`, but instead of reserving a maximum size at boot, the plugin is asked for the exact size of the file
+/// every time the game is about to load it
+#[no_mangle]
+pub extern "C" fn arcrop_register_callback_with_size(hash: Hash40, size_cb: SizeCallbackFn, cb: CallbackFn) {
+    debug!(
+        "arcrop_register_callback_with_size -> Hash received: {} ({:#x})",
+        hashes::find(hash).green(),
+        hash.0
+    );
+
+    let request = PendingApiCall::SizedCallback {
+        hash,
+        size_callback: size_cb,
+        callback: cb,
+    };
+
+    let mut pending_calls = PENDING_CALLBACKS.lock();
+
+    if GlobalFilesystem::is_init() {
+        crate::GLOBAL_FILESYSTEM.write().handle_api_request(request);
+    } else {
+        pending_calls.push(request);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn arcrop_register_callback_with_path(hash: Hash40, cb: StreamCallbackFn) {
     debug!(
@@ -60,6 +129,254 @@ pub extern "C" fn arcrop_register_callback_with_path(hash: Hash40, cb: StreamCal
 }
 
 #[no_mangle]
-pub extern "C" fn arcrop_register_extension_callback() {
-    error!("Extension callbacks are not (yet) supported in ARCropolis 3.0.0. Please contact the developer to have them update their plugin.");
+pub extern "C" fn arcrop_register_stream_data_callback(hash: Hash40, size: usize, cb: StreamDataCallbackFn) {
+    debug!(
+        "arcrop_register_stream_data_callback -> Hash received: {} ({:#x}), Size: {:#x}",
+        hashes::find(hash).green(),
+        hash.0,
+        size
+    );
+
+    let request = PendingApiCall::StreamDataCallback { hash, size, callback: cb };
+
+    let mut pending_calls = PENDING_CALLBACKS.lock();
+
+    if GlobalFilesystem::is_init() {
+        crate::GLOBAL_FILESYSTEM.write().handle_api_request(request);
+    } else {
+        pending_calls.push(request);
+    }
+}
+
+/// Removes the most recent registration of a callback for a file, whether it is still pending or already in the filesystem.
+/// The filesystem is locked while loading files, so this must not be called from inside a callback.
+fn unregister_callback(hash: Hash40, address: usize) -> bool {
+    let mut pending_calls = PENDING_CALLBACKS.lock();
+
+    if GlobalFilesystem::is_init() {
+        crate::GLOBAL_FILESYSTEM.write().remove_api_callback(hash, address)
+    } else {
+        match pending_calls
+            .iter()
+            .rposition(|call| call.hash() == hash && call.callback_address() == Some(address))
+        {
+            Some(index) => {
+                pending_calls.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn arcrop_unregister_callback(hash: Hash40, cb: CallbackFn) -> bool {
+    debug!(
+        "arcrop_unregister_callback -> Hash received: {} ({:#x})",
+        hashes::find(hash).green(),
+        hash.0
+    );
+
+    unregister_callback(hash, cb as usize)
+}
+
+#[no_mangle]
+pub extern "C" fn arcrop_unregister_callback_with_path(hash: Hash40, cb: StreamCallbackFn) -> bool {
+    debug!(
+        "arcrop_unregister_callback_with_path -> Hash received: {} ({:#x})",
+        hashes::find(hash).green(),
+        hash.0
+    );
+
+    unregister_callback(hash, cb as usize)
+}
+
+#[no_mangle]
+pub extern "C" fn arcrop_unregister_stream_data_callback(hash: Hash40, cb: StreamDataCallbackFn) -> bool {
+    debug!(
+        "arcrop_unregister_stream_data_callback -> Hash received: {} ({:#x})",
+        hashes::find(hash).green(),
+        hash.0
+    );
+
+    unregister_callback(hash, cb as usize)
+}
+
+/// Swaps a callback registered with `arcrop_register_callback` for a new one, keeping its place in the chain of the file.
+/// Same as unregistering, this must not be called from inside a callback.
+#[no_mangle]
+pub extern "C" fn arcrop_replace_callback(hash: Hash40, old_cb: CallbackFn, new_cb: CallbackFn, max_size: usize) -> bool {
+    debug!(
+        "arcrop_replace_callback -> Hash received: {} ({:#x}), Max size: {:#x}",
+        hashes::find(hash).green(),
+        hash.0,
+        max_size
+    );
+
+    let mut pending_calls = PENDING_CALLBACKS.lock();
+
+    if GlobalFilesystem::is_init() {
+        crate::GLOBAL_FILESYSTEM
+            .write()
+            .replace_api_callback(hash, old_cb as usize, ApiCallback::GenericCallback(new_cb), Some(max_size))
+    } else {
+        let pending = pending_calls.iter_mut().rev().find_map(|call| match call {
+            PendingApiCall::GenericCallback {
+                hash: call_hash,
+                max_size: call_max_size,
+                callback,
+            } if *call_hash == hash && *callback as usize == old_cb as usize => Some((call_max_size, callback)),
+            _ => None,
+        });
+
+        match pending {
+            Some((call_max_size, callback)) => {
+                *call_max_size = max_size;
+                *callback = new_cb;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// Swaps a callback registered with `arcrop_register_callback_with_path` for a new one, keeping its place in the chain of the file.
+/// Same as unregistering, this must not be called from inside a callback.
+#[no_mangle]
+pub extern "C" fn arcrop_replace_callback_with_path(hash: Hash40, old_cb: StreamCallbackFn, new_cb: StreamCallbackFn) -> bool {
+    debug!(
+        "arcrop_replace_callback_with_path -> Hash received: {} ({:#x})",
+        hashes::find(hash).green(),
+        hash.0
+    );
+
+    let mut pending_calls = PENDING_CALLBACKS.lock();
+
+    if GlobalFilesystem::is_init() {
+        crate::GLOBAL_FILESYSTEM
+            .write()
+            .replace_api_callback(hash, old_cb as usize, ApiCallback::StreamCallback(new_cb), None)
+    } else {
+        let pending = pending_calls.iter_mut().rev().find_map(|call| match call {
+            PendingApiCall::StreamCallback { hash: call_hash, callback } if *call_hash == hash && *callback as usize == old_cb as usize => {
+                Some(callback)
+            },
+            _ => None,
+        });
+
+        match pending {
+            Some(callback) => {
+                *callback = new_cb;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// Adds a file to the filesystem whose data is a copy of the provided buffer. Adding the same file again replaces its data.
+///
+/// Files that are not in the data.arc are added to it the same way new files from mods are, which happens when the mods are processed.
+/// They can be added until then, including from a synchronous `ProcessModsStarting` event callback, but are refused afterwards.
+/// Prefer `arcrop_add_virtual_file_with_path` for those, so that their path is known.
+#[no_mangle]
+pub extern "C" fn arcrop_add_virtual_file(hash: Hash40, data: *const u8, size: usize) {
+    debug!(
+        "arcrop_add_virtual_file -> Hash received: {} ({:#x}), Size: {:#x}",
+        hashes::find(hash).green(),
+        hash.0,
+        size
+    );
+
+    let data: Arc<[u8]> = if data.is_null() || size == 0 {
+        Arc::from(Vec::new())
+    } else {
+        Arc::from(unsafe { std::slice::from_raw_parts(data, size) })
+    };
+
+    let request = PendingApiCall::VirtualFile { hash, data };
+
+    let mut pending_calls = PENDING_CALLBACKS.lock();
+
+    if GlobalFilesystem::is_init() {
+        // Mods are processed while holding the filesystem lock, so check under it to not miss them by a hair
+        let mut filesystem = crate::GLOBAL_FILESYSTEM.write();
+
+        if GlobalFilesystem::are_mods_processed() && crate::resource::arc().get_file_path_index_from_hash(hash).is_err() {
+            error!(
+                "Cannot add virtual file '{}' ({:#x}) because it is not in the data.arc and the mods were already processed.",
+                hashes::find(hash),
+                hash.0
+            );
+            return;
+        }
+
+        filesystem.handle_api_request(request);
+    } else {
+        pending_calls.push(request);
+    }
+}
+
+/// Same as `arcrop_add_virtual_file`, but with the full path of the file so that files that are not in the data.arc can be added
+#[no_mangle]
+pub extern "C" fn arcrop_add_virtual_file_with_path(path: *const c_char, data: *const u8, size: usize) {
+    let path = unsafe { skyline::from_c_str(path) };
+
+    debug!("arcrop_add_virtual_file_with_path -> Path received: {}", path.green());
+
+    // New files need their path known, since they are added to the data.arc the same way new files from mods are
+    hashes::add(&path);
+    arcrop_add_virtual_file(Hash40::from(path.as_str()), data, size);
+}
+
+#[no_mangle]
+pub extern "C" fn arcrop_register_extension_callback(ext: Hash40, cb: ExtensionCallbackFn) {
+    debug!(
+        "arcrop_register_extension_callback -> Extension received: {} ({:#x})",
+        hashes::find(ext).green(),
+        ext.0
+    );
+
+    EXTENSION_CALLBACKS.write().entry(ext).or_default().push(cb);
+}
+
+/// Checks if files with this extension have to go through extension callbacks when loaded
+pub fn has_extension_callbacks(ext: Hash40) -> bool {
+    EXTENSION_CALLBACKS.read().contains_key(&ext)
+}
+
+/// Runs every callback registered for the extension over the loaded data, each one getting the output of the previous one.
+/// Returns the final size of the data.
+pub fn run_extension_callbacks(hash: Hash40, ext: Hash40, buffer: &mut [u8], size: usize) -> usize {
+    // Copied out so that the lock is not held while calling into plugins, which are free to register more callbacks
+    let callbacks = if let Some(callbacks) = EXTENSION_CALLBACKS.read().get(&ext) {
+        callbacks.clone()
+    } else {
+        return size;
+    };
+
+    let mut size = size;
+
+    for cb in callbacks.iter() {
+        let mut new_size = size;
+
+        if !cb(hash.0, buffer.as_mut_ptr(), buffer.len(), &mut new_size) {
+            continue;
+        }
+
+        if new_size > buffer.len() {
+            error!(
+                "Extension callback for '{}' ({:#x}) reported a size of {:#x}, bigger than the {:#x} bytes buffer. Ignoring the new size.",
+                hashes::find(hash),
+                hash.0,
+                new_size,
+                buffer.len()
+            );
+            continue;
+        }
+
+        size = new_size;
+    }
+
+    size
 }
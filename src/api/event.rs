@@ -1,70 +1,287 @@
+use std::{collections::VecDeque, ffi::CString, path::Path};
+
 use arcropolis_api::{Event, EventCallbackFn};
 use once_cell::sync::Lazy;
-use parking_lot::RwLock;
+use parking_lot::{Condvar, Mutex, RwLock};
+use skyline::libc::c_char;
+use smash_arc::Hash40;
 
-pub struct EventCallbacks {
-    arc_fs_mounted: Vec<EventCallbackFn>,
-    mod_fs_mounted: Vec<EventCallbackFn>,
+/// Every event that can be listened to through `arcrop_register_event_callback_v2`.
+/// New kinds are only ever appended, so that the values stay the same for plugins built against older versions.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    ArcFilesystemMounted = 0,
+    ModFilesystemMounted = 1,
+    /// Discovery is over. The payload lists the root of every enabled mod.
+    DiscoveryFinished = 2,
+    ProcessModsStarting = 3,
+    ProcessModsFinished = 4,
+    /// A file was replaced in the game's memory. The payload holds its hash and size.
+    FileReplaced = 5,
+    /// The active workspace changed, or the preset of a workspace was edited. The payload holds the name of the workspace.
+    WorkspaceChanged = 6,
 }
 
-impl EventCallbacks {
-    pub fn new() -> Self {
-        EventCallbacks {
-            arc_fs_mounted: vec![],
-            mod_fs_mounted: vec![],
+impl EventKind {
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::ArcFilesystemMounted),
+            1 => Some(Self::ModFilesystemMounted),
+            2 => Some(Self::DiscoveryFinished),
+            3 => Some(Self::ProcessModsStarting),
+            4 => Some(Self::ProcessModsFinished),
+            5 => Some(Self::FileReplaced),
+            6 => Some(Self::WorkspaceChanged),
+            _ => None,
+        }
+    }
+
+    fn to_legacy(self) -> Option<Event> {
+        match self {
+            Self::ArcFilesystemMounted => Some(Event::ArcFilesystemMounted),
+            Self::ModFilesystemMounted => Some(Event::ModFilesystemMounted),
+            _ => None,
         }
     }
 }
 
-pub static EVENT_CALLBACKS: Lazy<RwLock<EventCallbacks>> = Lazy::new(|| RwLock::new(EventCallbacks::new()));
-pub static EVENT_QUEUE: Lazy<RwLock<Vec<Event>>> = Lazy::new(|| RwLock::new(Vec::new()));
+impl From<Event> for EventKind {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::ArcFilesystemMounted => Self::ArcFilesystemMounted,
+            Event::ModFilesystemMounted => Self::ModFilesystemMounted,
+        }
+    }
+}
 
-impl std::ops::Index<Event> for EventCallbacks {
-    type Output = Vec<EventCallbackFn>;
+/// What is handed to the callbacks registered through `arcrop_register_event_callback_v2` and `arcrop_register_event_callback_with_mode`. Fields that do not apply to the event are zeroed.
+#[repr(C)]
+pub struct EventInfo {
+    pub kind: EventKind,
+    pub hash: u64,
+    pub size: usize,
+    /// Null-terminated strings, only valid for the duration of the callback
+    pub strings: *const *const c_char,
+    pub string_count: usize,
+}
 
-    fn index(&self, index: Event) -> &Self::Output {
-        match index {
-            Event::ArcFilesystemMounted => &self.arc_fs_mounted,
-            Event::ModFilesystemMounted => &self.mod_fs_mounted,
+pub type EventCallbackV2Fn = extern "C" fn(&EventInfo);
+
+/// Same as [`EventCallbackV2Fn`], but returns false if the plugin failed to handle the event
+pub type EventResultCallbackFn = extern "C" fn(&EventInfo) -> bool;
+
+/// Where a callback is run
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchMode {
+    /// On the thread that sent the event, before the sender moves on
+    Synchronous = 0,
+    /// On the event thread, some time after the event was sent
+    Asynchronous = 1,
+}
+
+#[derive(Clone, Copy)]
+enum EventCallback {
+    Legacy(EventCallbackFn),
+    V2(EventCallbackV2Fn),
+    WithResult(EventResultCallbackFn),
+}
+
+struct Registration {
+    kind: EventKind,
+    mode: DispatchMode,
+    callback: EventCallback,
+}
+
+/// An event waiting to be dispatched, owning its payload
+pub struct QueuedEvent {
+    kind: EventKind,
+    hash: Hash40,
+    size: usize,
+    strings: Vec<CString>,
+}
+
+impl QueuedEvent {
+    pub fn new(kind: EventKind) -> Self {
+        Self {
+            kind,
+            hash: Hash40(0),
+            size: 0,
+            strings: Vec::new(),
         }
     }
-}
 
-impl std::ops::IndexMut<Event> for EventCallbacks {
-    fn index_mut(&mut self, index: Event) -> &mut Self::Output {
-        match index {
-            Event::ArcFilesystemMounted => &mut self.arc_fs_mounted,
-            Event::ModFilesystemMounted => &mut self.mod_fs_mounted,
+    pub fn discovery_finished(mod_roots: &[&Path]) -> Self {
+        let mut event = Self::new(EventKind::DiscoveryFinished);
+        event.strings = mod_roots
+            .iter()
+            .filter_map(|root| CString::new(root.to_string_lossy().into_owned()).ok())
+            .collect();
+        event
+    }
+
+    pub fn file_replaced(hash: Hash40, size: usize) -> Self {
+        let mut event = Self::new(EventKind::FileReplaced);
+        event.hash = hash;
+        event.size = size;
+        event
+    }
+
+    pub fn workspace_changed(name: &str) -> Self {
+        let mut event = Self::new(EventKind::WorkspaceChanged);
+        event.strings = CString::new(name).into_iter().collect();
+        event
+    }
+
+    /// Runs every callback registered for this event with the given mode, in registration order.
+    /// Returns false if any of them reported a failure.
+    fn dispatch(&self, mode: DispatchMode) -> bool {
+        // Copy the callbacks out so that they are free to register more callbacks
+        let callbacks: Vec<EventCallback> = REGISTRATIONS
+            .read()
+            .iter()
+            .filter(|registration| registration.kind == self.kind && registration.mode == mode)
+            .map(|registration| registration.callback)
+            .collect();
+
+        if callbacks.is_empty() {
+            return true;
         }
+
+        let strings: Vec<*const c_char> = self.strings.iter().map(|string| string.as_ptr() as *const c_char).collect();
+
+        let info = EventInfo {
+            kind: self.kind,
+            hash: self.hash.0,
+            size: self.size,
+            strings: strings.as_ptr(),
+            string_count: strings.len(),
+        };
+
+        let mut success = true;
+
+        for callback in callbacks {
+            match callback {
+                EventCallback::Legacy(cb) => {
+                    if let Some(event) = self.kind.to_legacy() {
+                        cb(event);
+                    }
+                },
+                EventCallback::V2(cb) => cb(&info),
+                EventCallback::WithResult(cb) => {
+                    if !cb(&info) {
+                        warn!("A plugin reported a failure while handling event {:?}.", self.kind);
+                        success = false;
+                    }
+                },
+            }
+        }
+
+        success
     }
 }
 
+static REGISTRATIONS: Lazy<RwLock<Vec<Registration>>> = Lazy::new(|| RwLock::new(Vec::new()));
+static ASYNC_QUEUE: Lazy<Mutex<VecDeque<QueuedEvent>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+static ASYNC_QUEUE_CONDVAR: Condvar = Condvar::new();
+
+fn register(kind: EventKind, mode: DispatchMode, callback: EventCallback) {
+    REGISTRATIONS.write().push(Registration { kind, mode, callback });
+}
+
+fn is_registered(kind: EventKind, mode: DispatchMode) -> bool {
+    REGISTRATIONS
+        .read()
+        .iter()
+        .any(|registration| registration.kind == kind && registration.mode == mode)
+}
+
+// Events are delivered synchronously by default, so that plugins know nothing else happened since the event was sent.
+// Running on the event thread instead is opt-in, through arcrop_register_event_callback_with_mode.
 #[no_mangle]
 pub extern "C" fn arcrop_register_event_callback(ty: Event, callback: EventCallbackFn) {
-    let mut cbs = EVENT_CALLBACKS.write();
-    cbs[ty].push(callback);
+    register(ty.into(), DispatchMode::Synchronous, EventCallback::Legacy(callback));
+}
+
+/// The callback is run synchronously, on the thread sending the event. Returns false if the event kind is unknown to this version of ARCropolis.
+#[no_mangle]
+pub extern "C" fn arcrop_register_event_callback_v2(kind: u32, callback: EventCallbackV2Fn) -> bool {
+    debug!("arcrop_register_event_callback_v2 -> Kind received: {}", kind);
+
+    match EventKind::from_raw(kind) {
+        Some(kind) => {
+            register(kind, DispatchMode::Synchronous, EventCallback::V2(callback));
+            true
+        },
+        None => {
+            warn!("A plugin tried to register a callback for unknown event kind {}.", kind);
+            false
+        },
+    }
+}
+
+/// Returns false if the event kind or the dispatch mode is unknown to this version of ARCropolis
+#[no_mangle]
+pub extern "C" fn arcrop_register_event_callback_with_mode(kind: u32, mode: u32, callback: EventResultCallbackFn) -> bool {
+    debug!("arcrop_register_event_callback_with_mode -> Kind received: {}, Mode received: {}", kind, mode);
+
+    let mode = match mode {
+        0 => DispatchMode::Synchronous,
+        1 => DispatchMode::Asynchronous,
+        _ => {
+            warn!("A plugin tried to register a callback with unknown dispatch mode {}.", mode);
+            return false;
+        },
+    };
+
+    match EventKind::from_raw(kind) {
+        Some(kind) => {
+            register(kind, mode, EventCallback::WithResult(callback));
+            true
+        },
+        None => {
+            warn!("A plugin tried to register a callback for unknown event kind {}.", kind);
+            false
+        },
+    }
 }
 
 fn event_loop() {
     loop {
-        std::thread::sleep(std::time::Duration::from_millis(20));
-        let mut events = Vec::new();
-        let mut full_events = EVENT_QUEUE.write();
-        std::mem::swap(&mut events, &mut full_events);
-        drop(full_events);
+        let mut queue = ASYNC_QUEUE.lock();
+
+        while queue.is_empty() {
+            ASYNC_QUEUE_CONDVAR.wait(&mut queue);
+        }
 
-        let cbs = EVENT_CALLBACKS.read();
+        let events = std::mem::take(&mut *queue);
+        drop(queue);
 
         for e in events.into_iter() {
-            for cb in cbs[e].iter() {
-                cb(e);
-            }
+            e.dispatch(DispatchMode::Asynchronous);
         }
     }
 }
 
-pub fn send_event(e: Event) {
-    EVENT_QUEUE.write().push(e);
+pub fn send_event(e: Event) -> bool {
+    send_event_v2(QueuedEvent::new(e.into()))
+}
+
+/// Runs the synchronous callbacks of the event right away, then hands it to the event thread for the asynchronous ones.
+/// Returns false if a synchronous callback reported a failure.
+///
+/// Synchronous callbacks are free to call into the API, so this must not be called while holding the filesystem lock.
+pub fn send_event_v2(e: QueuedEvent) -> bool {
+    let success = e.dispatch(DispatchMode::Synchronous);
+
+    // Events like FileReplaced are sent for every loaded file, so don't keep them around if nothing is going to handle them
+    if is_registered(e.kind, DispatchMode::Asynchronous) {
+        ASYNC_QUEUE.lock().push_back(e);
+        ASYNC_QUEUE_CONDVAR.notify_one();
+    }
+
+    success
 }
 
 pub fn setup() {
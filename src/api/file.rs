@@ -2,7 +2,6 @@ use std::collections::{HashMap, HashSet};
 
 use owo_colors::OwoColorize;
 use smash_arc::*;
-use walkdir::WalkDir;
 
 use crate::{config, hashes, resource, utils};
 
@@ -29,6 +28,36 @@ pub extern "C" fn arcrop_load_file(hash: Hash40, out_buffer: *mut u8, buf_length
     }
 }
 
+/// Writes a null-terminated description of where the data of a file comes from: the mod root that replaces it, the patch files
+/// merged into it or the plugin callback that provides it.
+/// Returns false if the file is not in the filesystem or if the description does not fit in the buffer.
+#[no_mangle]
+pub extern "C" fn arcrop_get_file_source(hash: Hash40, out_buffer: *mut u8, buf_length: usize) -> bool {
+    debug!(
+        "arcrop_get_file_source -> Hash received: {} ({:#x}), Buffer len: {:#x}",
+        hashes::find(hash).green(),
+        hash.0,
+        buf_length
+    );
+
+    // Same as arcrop_load_file, this can be called by callbacks while the filesystem is locked
+    let source = match unsafe { (*crate::GLOBAL_FILESYSTEM.data_ptr()).get_file_source(hash) } {
+        Some(source) => source.to_string(),
+        None => return false,
+    };
+
+    if source.len() >= buf_length {
+        debug!("arcrop_get_file_source -> Buffer is too small for the source ({:#x} bytes)", source.len() + 1);
+        return false;
+    }
+
+    let buffer = unsafe { std::slice::from_raw_parts_mut(out_buffer, buf_length) };
+    buffer[..source.len()].copy_from_slice(source.as_bytes());
+    buffer[source.len()] = 0;
+
+    true
+}
+
 #[no_mangle]
 pub extern "C" fn arcrop_get_decompressed_size(hash: Hash40, out_size: &mut usize) -> bool {
     debug!(
@@ -68,37 +97,25 @@ pub extern "C" fn arcrop_is_file_loaded(hash: Hash40) -> bool {
 pub extern "C" fn arcrop_is_mod_enabled(hash: Hash40) -> bool {
     debug!("arcrop_is_mod_enabled -> Received hash {} ({:#x})", hashes::find(hash).green(), hash.0);
 
-    let storage = crate::config::GLOBAL_CONFIG.lock().unwrap();
+    // Discovery caches the enabled mods, only plugins asking before it is over need to go through the config
+    if let Some(is_enabled) = super::mods::is_enabled_cached(hash) {
+        return is_enabled;
+    }
 
-    let preset: HashSet<Hash40> = if storage.get_flag("legacy_discovery") || utils::env::is_emulator() {
-        WalkDir::new(crate::utils::paths::mods())
-            .max_depth(1)
+    // Listing the mod folders reads the config, so it cannot be done while holding the lock
+    let preset: HashSet<Hash40> = if config::legacy_discovery() || utils::env::is_emulator() {
+        utils::paths::mod_roots()
             .into_iter()
-            .filter_map(|entry| {
-                if let Ok(entry) = entry {
-                    // Make this less gross
-                    if !entry.file_type().is_dir() {
-                        return None;
-                    }
-
-                    let path = entry.path();
-
-                    if path
-                        .file_name()
-                        .and_then(|name| name.to_str())
-                        .map(|name| !name.starts_with('.'))
-                        .unwrap_or(false)
-                    {
-                        Some(Hash40::from(path.to_str().unwrap()))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| !name.starts_with('.'))
+                    .unwrap_or(false)
             })
+            .map(|path| Hash40::from(path.to_str().unwrap()))
             .collect()
     } else {
+        let storage = crate::config::GLOBAL_CONFIG.lock().unwrap();
         let workspace_name: String = storage.get_field("workspace").unwrap_or_else(|_| "Default".to_string());
         let workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
         let preset_name = &workspace_list[&workspace_name];
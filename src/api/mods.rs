@@ -0,0 +1,150 @@
+use std::{
+    ffi::CString,
+    path::{Path, PathBuf},
+};
+
+use camino::Utf8PathBuf;
+use once_cell::sync::Lazy;
+use owo_colors::OwoColorize;
+use parking_lot::RwLock;
+use skyline::libc::c_char;
+use smash_arc::Hash40;
+
+use crate::{hashes, menus::arcadia, utils};
+
+/// The fields of a mod's info.toml, as given to plugins. Every string is null-terminated and owned by ARCropolis.
+#[repr(C)]
+pub struct ModInfo {
+    pub path: *const c_char,
+    pub folder_name: *const c_char,
+    pub display_name: *const c_char,
+    pub authors: *const c_char,
+    pub version: *const c_char,
+    pub description: *const c_char,
+    pub category: *const c_char,
+}
+
+/// An enabled mod root and its info.toml fields, kept around so that the pointers given to plugins stay valid
+struct EnabledMod {
+    hash: Hash40,
+    path: CString,
+    folder_name: CString,
+    display_name: CString,
+    authors: CString,
+    version: CString,
+    description: CString,
+    category: CString,
+}
+
+impl EnabledMod {
+    fn new(id: u32, path: &Path, folders: &[Utf8PathBuf]) -> Self {
+        let entry = match arcadia::read_entry(id, path, false, folders) {
+            Ok(entry) => entry,
+            Err((entry, e)) => {
+                warn!("The info.toml of '{}' is not valid. Reason: {}", path.display(), e);
+                entry
+            },
+        };
+
+        // Interior null bytes would make the whole string unusable, so strip them instead
+        fn c_string(string: Option<String>) -> CString {
+            CString::new(string.unwrap_or_default().replace('\0', "")).unwrap()
+        }
+
+        Self {
+            hash: Hash40::from(path.to_str().unwrap_or_default()),
+            path: c_string(path.to_str().map(String::from)),
+            folder_name: c_string(entry.folder_name),
+            display_name: c_string(entry.display_name),
+            authors: c_string(entry.authors),
+            version: c_string(entry.version),
+            description: c_string(entry.description),
+            category: c_string(entry.category),
+        }
+    }
+
+    fn to_info(&self) -> ModInfo {
+        ModInfo {
+            path: self.path.as_ptr(),
+            folder_name: self.folder_name.as_ptr(),
+            display_name: self.display_name.as_ptr(),
+            authors: self.authors.as_ptr(),
+            version: self.version.as_ptr(),
+            description: self.description.as_ptr(),
+            category: self.category.as_ptr(),
+        }
+    }
+}
+
+// Computed once when discovery is over. It is never replaced afterwards, so the strings handed out to plugins live for the rest of the game.
+static ENABLED_MODS: Lazy<RwLock<Option<Vec<EnabledMod>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Caches the mod roots enabled for the active workspace. Only the first call has any effect.
+pub fn set_enabled_mods(paths: &[&Path]) {
+    let mut enabled_mods = ENABLED_MODS.write();
+
+    if enabled_mods.is_some() {
+        return;
+    }
+
+    let mut paths: Vec<PathBuf> = paths.iter().map(|path| path.to_path_buf()).collect();
+    paths.sort();
+
+    let folders = utils::paths::mod_folders();
+
+    *enabled_mods = Some(
+        paths
+            .iter()
+            .enumerate()
+            .map(|(id, path)| EnabledMod::new(id as u32, path, &folders))
+            .collect(),
+    );
+}
+
+/// Checks if a mod root is enabled, or returns None if discovery has not been performed yet
+pub fn is_enabled_cached(hash: Hash40) -> Option<bool> {
+    ENABLED_MODS.read().as_ref().map(|enabled_mods| enabled_mods.iter().any(|enabled_mod| enabled_mod.hash == hash))
+}
+
+#[no_mangle]
+pub extern "C" fn arcrop_get_enabled_mod_count() -> usize {
+    debug!("arcrop_get_enabled_mod_count -> Function called");
+
+    ENABLED_MODS.read().as_ref().map_or(0, |enabled_mods| enabled_mods.len())
+}
+
+/// Fills `out_info` with the info.toml fields of the enabled mod at `index`, ordered by path.
+/// Returns false if discovery has not been performed yet or if the index is out of bounds.
+#[no_mangle]
+pub extern "C" fn arcrop_get_enabled_mod_info(index: usize, out_info: &mut ModInfo) -> bool {
+    debug!("arcrop_get_enabled_mod_info -> Received index {}", index);
+
+    match ENABLED_MODS.read().as_ref().and_then(|enabled_mods| enabled_mods.get(index)) {
+        Some(enabled_mod) => {
+            *out_info = enabled_mod.to_info();
+            true
+        },
+        None => false,
+    }
+}
+
+/// Fills `out_info` with the info.toml fields of an enabled mod, looked up by the hash of its full path.
+/// Returns false if the mod is not enabled or discovery has not been performed yet.
+#[no_mangle]
+pub extern "C" fn arcrop_get_mod_info(hash: Hash40, out_info: &mut ModInfo) -> bool {
+    debug!("arcrop_get_mod_info -> Received hash {} ({:#x})", hashes::find(hash).green(), hash.0);
+
+    let enabled_mods = ENABLED_MODS.read();
+
+    let enabled_mod = enabled_mods
+        .as_ref()
+        .and_then(|enabled_mods| enabled_mods.iter().find(|enabled_mod| enabled_mod.hash == hash));
+
+    match enabled_mod {
+        Some(enabled_mod) => {
+            *out_info = enabled_mod.to_info();
+            true
+        },
+        None => false,
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    ffi::CString,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use skyline::libc::c_char;
+
+use crate::{
+    fs::patches::{self, PatchFile, PatchHandler},
+    PathExtension,
+};
+
+/// Builds a patched file for a plugin-registered format.
+///
+/// Arguments, in order: the hash of the patched file, the base data and its size, the null-terminated paths of every patch file
+/// targeting it and their count, then the output buffer, its size and where to write the size of the patched file.
+pub type PatchMergeFn = extern "C" fn(u64, *const u8, usize, *const *const c_char, usize, *mut u8, usize, &mut usize) -> bool;
+
+/// A patch format taught to ARCropolis by a plugin
+struct PluginPatchFormat {
+    // Formats are registered once and live for the rest of the game, so the strings are leaked to hand out static slices
+    name: &'static str,
+    extensions: [&'static str; 1],
+    target_extension: &'static str,
+    merge_fn: PatchMergeFn,
+}
+
+impl PatchHandler for PluginPatchFormat {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &self.extensions
+    }
+
+    fn target_path(&self, local: &Path) -> PathBuf {
+        local.with_extension(self.target_extension)
+    }
+
+    fn patch(&self, local: &Path, base: Vec<u8>, patches: &[PatchFile]) -> Result<Vec<u8>, String> {
+        let hash = local.smash_hash().map_err(|e| format!("{:?}", e))?;
+
+        let paths = patches
+            .iter()
+            .map(|patch| CString::new(patch.path.to_string_lossy().into_owned()).map_err(|e| format!("{:?}", e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let path_ptrs: Vec<*const c_char> = paths.iter().map(|path| path.as_ptr() as *const c_char).collect();
+
+        // Patched files get 10 times the size of their vanilla counterpart reserved, so that's what the plugin gets to work with
+        let mut out = vec![0u8; base.len() * 10];
+        let mut out_size = 0;
+
+        if !(self.merge_fn)(hash.0, base.as_ptr(), base.len(), path_ptrs.as_ptr(), path_ptrs.len(), out.as_mut_ptr(), out.len(), &mut out_size) {
+            return Err(format!("Plugin merge function for .{} files did not patch the file!", self.extensions[0]));
+        }
+
+        if out_size > out.len() {
+            return Err(format!(
+                "Plugin merge function for .{} files reported a size of {:#x}, bigger than the {:#x} bytes buffer!",
+                self.extensions[0],
+                out_size,
+                out.len()
+            ));
+        }
+
+        out.truncate(out_size);
+        Ok(out)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn arcrop_register_patch_format(ext: *const c_char, target_ext: *const c_char, merge_fn: PatchMergeFn) -> bool {
+    let ext = unsafe { skyline::from_c_str(ext) };
+    let target_ext = unsafe { skyline::from_c_str(target_ext) };
+
+    // Extensions are accepted with or without their leading dot
+    let ext = ext.trim_start_matches('.').to_string();
+    let target_ext = target_ext.trim_start_matches('.').to_string();
+
+    debug!("arcrop_register_patch_format -> Extension received: {}, target extension: {}", ext, target_ext);
+
+    if ext.is_empty() || target_ext.is_empty() {
+        error!("Cannot register a patch format without an extension or a target extension.");
+        return false;
+    }
+
+    // Named after the extension, but kept apart from the names of the builtin formats, which are named after the file they patch
+    let format = PluginPatchFormat {
+        name: Box::leak(format!("plugin-{}", ext).into_boxed_str()),
+        extensions: [Box::leak(ext.clone().into_boxed_str())],
+        target_extension: Box::leak(target_ext.into_boxed_str()),
+        merge_fn,
+    };
+
+    match patches::register_handler(Arc::new(format)) {
+        Ok(_) => {
+            info!("Registered patch format for .{} files.", ext);
+            true
+        },
+        Err(e) => {
+            error!("Cannot register a patch format for .{} files, {}.", ext, e);
+            false
+        },
+    }
+}
@@ -0,0 +1,135 @@
+use skyline::libc::c_char;
+
+use crate::menus::plugins::{self, PluginOption, PluginOptionKind, PluginPage};
+
+/// The kind of an option of a plugin settings page
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SettingKind {
+    Flag = 0,
+    Choice = 1,
+}
+
+/// An option of a plugin settings page, as described by the plugin.
+/// `choices` is only read for `SettingKind::Choice`, and its first entry is the default value.
+#[repr(C)]
+pub struct SettingOption {
+    pub kind: u32,
+    pub key: *const c_char,
+    pub label: *const c_char,
+    pub choices: *const *const c_char,
+    pub choice_count: usize,
+}
+
+fn read_option(option: &SettingOption) -> Option<PluginOption> {
+    if option.key.is_null() || option.label.is_null() {
+        return None;
+    }
+
+    let key = unsafe { skyline::from_c_str(option.key) };
+    let label = unsafe { skyline::from_c_str(option.label) };
+
+    if !plugins::is_valid_name(&key) {
+        error!("Plugin setting '{}' has an invalid key. Only letters, digits, '_' and '-' are allowed.", key);
+        return None;
+    }
+
+    let kind = match option.kind {
+        x if x == SettingKind::Flag as u32 => PluginOptionKind::Flag,
+        x if x == SettingKind::Choice as u32 && !option.choices.is_null() && option.choice_count != 0 => {
+            let choices = unsafe { std::slice::from_raw_parts(option.choices, option.choice_count) };
+
+            if choices.iter().any(|choice| choice.is_null()) {
+                error!("Plugin setting '{}' has a null choice.", key);
+                return None;
+            }
+
+            PluginOptionKind::Choice {
+                choices: choices.iter().map(|choice| unsafe { skyline::from_c_str(*choice) }).collect(),
+            }
+        },
+        _ => {
+            error!("Plugin setting '{}' has an invalid kind or no choices.", key);
+            return None;
+        },
+    };
+
+    Some(PluginOption { key, label, kind })
+}
+
+/// Adds a settings page to the ARCropolis menu. Its options are stored in the config storage under `namespace`.
+/// Returns false if the page is invalid or the namespace is already used by another page.
+#[no_mangle]
+pub extern "C" fn arcrop_register_settings_page(
+    namespace: *const c_char,
+    title: *const c_char,
+    options: *const SettingOption,
+    option_count: usize,
+) -> bool {
+    if namespace.is_null() || title.is_null() || (options.is_null() && option_count != 0) {
+        return false;
+    }
+
+    let namespace = unsafe { skyline::from_c_str(namespace) };
+    let title = unsafe { skyline::from_c_str(title) };
+
+    debug!("arcrop_register_settings_page -> Namespace: {}, Option count: {}", namespace, option_count);
+
+    if !plugins::is_valid_name(&namespace) {
+        error!("Plugin settings namespace '{}' is invalid. Only letters, digits, '_' and '-' are allowed.", namespace);
+        return false;
+    }
+
+    let options = if option_count == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(options, option_count) }
+    };
+
+    let options: Option<Vec<PluginOption>> = options.iter().map(read_option).collect();
+
+    match options {
+        Some(options) => plugins::register_page(PluginPage { namespace, title, options }),
+        None => false,
+    }
+}
+
+/// Gets the value of a flag option of a settings page, which is off by default
+#[no_mangle]
+pub extern "C" fn arcrop_get_setting_flag(namespace: *const c_char, key: *const c_char) -> bool {
+    if namespace.is_null() || key.is_null() {
+        return false;
+    }
+
+    let namespace = unsafe { skyline::from_c_str(namespace) };
+    let key = unsafe { skyline::from_c_str(key) };
+
+    plugins::get_flag(&namespace, &key)
+}
+
+/// Writes the null-terminated value of a choice option of a settings page, which is its first choice by default.
+/// Returns false if the option does not exist or the value does not fit in the buffer.
+#[no_mangle]
+pub extern "C" fn arcrop_get_setting_value(namespace: *const c_char, key: *const c_char, out_buffer: *mut u8, buf_length: usize) -> bool {
+    if namespace.is_null() || key.is_null() {
+        return false;
+    }
+
+    let namespace = unsafe { skyline::from_c_str(namespace) };
+    let key = unsafe { skyline::from_c_str(key) };
+
+    let value = match plugins::get_option_choice(&namespace, &key) {
+        Some(value) => value,
+        None => return false,
+    };
+
+    if value.len() >= buf_length {
+        return false;
+    }
+
+    let buffer = unsafe { std::slice::from_raw_parts_mut(out_buffer, buf_length) };
+    buffer[..value.len()].copy_from_slice(value.as_bytes());
+    buffer[value.len()] = 0;
+
+    true
+}
@@ -7,6 +7,7 @@ use std::{
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
 use semver::Version;
+use serde::{Deserialize, Serialize};
 use skyline::nn;
 use skyline_config::*;
 use smash_arc::{Hash40, Region};
@@ -135,6 +136,63 @@ pub fn use_folder_name() -> bool {
     GLOBAL_CONFIG.lock().unwrap().get_flag("use_folder_name")
 }
 
+/// A folder that mods are discovered in. When mods of different folders provide the same file, the folder with the highest priority wins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModFolder {
+    pub path: String,
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// Sorts mod folders from the highest priority to the lowest, and drops the ones that are configured twice or are inside of
+/// another mod folder, since their mods would be discovered twice. Of two overlapping folders, the one with the highest priority is kept.
+fn normalize_mod_folders(mut folders: Vec<ModFolder>) -> Vec<ModFolder> {
+    for folder in folders.iter_mut() {
+        folder.path = folder.path.trim().trim_end_matches('/').to_string();
+    }
+
+    folders.sort_by(|a, b| b.priority.cmp(&a.priority));
+
+    let mut kept: Vec<ModFolder> = Vec::with_capacity(folders.len());
+
+    for folder in folders.into_iter().filter(|folder| !folder.path.is_empty()) {
+        let path = std::path::Path::new(&folder.path);
+
+        match kept
+            .iter()
+            .find(|other| path.starts_with(&other.path) || std::path::Path::new(&other.path).starts_with(path))
+        {
+            Some(other) => warn!(
+                "Mod folder '{}' is ignored because it overlaps with mod folder '{}'.",
+                folder.path, other.path
+            ),
+            None => kept.push(folder),
+        }
+    }
+
+    kept
+}
+
+/// Gets the folders that mods are discovered in, from the highest priority to the lowest.
+/// Falls back to the default mods folder if none are configured.
+pub fn mod_folders() -> Vec<ModFolder> {
+    resolve_mod_folders(GLOBAL_CONFIG.lock().unwrap().get_field_json("mod_folders").unwrap_or_default())
+}
+
+/// Normalizes the configured mod folders, falling back to the default mods folder if none are left
+pub fn resolve_mod_folders(folders: Vec<ModFolder>) -> Vec<ModFolder> {
+    let mut folders = normalize_mod_folders(folders);
+
+    if folders.is_empty() {
+        folders.push(ModFolder {
+            path: crate::utils::paths::mods().to_string(),
+            priority: 0,
+        });
+    }
+
+    folders
+}
+
 pub fn set_mod_cache(cache: &HashSet<Hash40>) -> Result<(), ConfigError> {
     GLOBAL_CONFIG.lock().unwrap().set_field_json("mod_cache", &cache)
 }
@@ -297,6 +355,163 @@ pub mod presets {
     }
 }
 
+pub mod priorities {
+    use super::*;
+
+    use std::{cmp::Reverse, path::Path};
+
+    use camino::Utf8PathBuf;
+    use smash_arc::Hash40;
+
+    use super::workspaces::WorkspaceError;
+
+    /// The fields of a mod's info.toml that matter for its priority
+    #[derive(Debug, Default, Deserialize)]
+    struct PriorityInfo {
+        #[serde(default)]
+        priority: i32,
+    }
+
+    /// Gets the priority declared in the info.toml of a mod root, defaulting to 0.
+    /// It only places the mods that the priority of the workspace does not rank yet.
+    pub fn declared_priority(root: &Path) -> i32 {
+        std::fs::read_to_string(root.join("info.toml"))
+            .ok()
+            .and_then(|info| toml::from_str::<PriorityInfo>(&info).ok())
+            .unwrap_or_default()
+            .priority
+    }
+
+    // Stored next to the preset of the workspace, so that renaming the workspace keeps it
+    fn priority_field(preset_name: &str) -> String {
+        format!("{}_priority", preset_name)
+    }
+
+    /// Gets the mod priority of the active workspace, highest priority first
+    pub fn get_active_priority() -> Result<Vec<Hash40>, WorkspaceError> {
+        let preset_name = workspaces::get_active_workspace()?;
+        GLOBAL_CONFIG
+            .lock()
+            .unwrap()
+            .get_field_json(priority_field(&preset_name))
+            .map_err(WorkspaceError::ConfigError)
+    }
+
+    pub fn get_priority(workspace_name: &str) -> Result<Vec<Hash40>, WorkspaceError> {
+        let preset_name = workspaces::get_workspace_by_name(workspace_name)?;
+        GLOBAL_CONFIG
+            .lock()
+            .unwrap()
+            .get_field_json(priority_field(&preset_name))
+            .map_err(WorkspaceError::ConfigError)
+    }
+
+    pub fn replace_priority(workspace_name: &str, priority: &[Hash40]) -> Result<(), WorkspaceError> {
+        let preset_name = workspaces::get_workspace_by_name(workspace_name)?;
+        GLOBAL_CONFIG
+            .lock()
+            .unwrap()
+            .set_field_json(priority_field(&preset_name), &priority)
+            .map_err(WorkspaceError::ConfigError)
+    }
+
+    /// Sorts mods from the highest priority to the lowest, given the path of their mod root.
+    /// Mods are first ordered by the priority of their mod folder, then by the priority of the workspace.
+    /// Mods missing from the priority of the workspace come last in their folder, ordered by the priority declared in their info.toml and then by path.
+    pub fn sort_by_priority<T, F: Fn(&T) -> PathBuf>(mods: &mut [T], priority: &[Hash40], root: F) {
+        sort_in_folders(mods, priority, &crate::utils::paths::mod_folders(), root);
+    }
+
+    fn sort_in_folders<T, F: Fn(&T) -> PathBuf>(mods: &mut [T], priority: &[Hash40], folders: &[Utf8PathBuf], root: F) {
+        mods.sort_by_cached_key(|item| {
+            let path = root(item);
+            let hash = Hash40::from(path.to_str().unwrap_or_default());
+            let folder_rank = crate::utils::paths::mod_folder_of(&path, folders)
+                .and_then(|folder| folders.iter().position(|other| other == folder))
+                .unwrap_or(usize::MAX);
+            let rank = priority.iter().position(|entry| *entry == hash).unwrap_or(usize::MAX);
+            let declared = if rank == usize::MAX { declared_priority(&path) } else { 0 };
+
+            (folder_rank, rank, Reverse(declared), path)
+        });
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn hash(path: &str) -> Hash40 {
+            Hash40::from(path)
+        }
+
+        fn sort(mods: &[&str], priority: &[&str], folders: &[&str]) -> Vec<String> {
+            let mut mods: Vec<PathBuf> = mods.iter().map(PathBuf::from).collect();
+            let priority: Vec<Hash40> = priority.iter().map(|path| hash(path)).collect();
+            let folders: Vec<Utf8PathBuf> = folders.iter().map(Utf8PathBuf::from).collect();
+
+            sort_in_folders(&mut mods, &priority, &folders, PathBuf::clone);
+            mods.into_iter().map(|path| path.to_string_lossy().into_owned()).collect()
+        }
+
+        #[test]
+        fn follows_the_priority_of_the_workspace() {
+            let sorted = sort(
+                &["sd:/ultimate/mods/a", "sd:/ultimate/mods/b", "sd:/ultimate/mods/c"],
+                &["sd:/ultimate/mods/c", "sd:/ultimate/mods/a", "sd:/ultimate/mods/b"],
+                &["sd:/ultimate/mods"],
+            );
+
+            assert_eq!(sorted, ["sd:/ultimate/mods/c", "sd:/ultimate/mods/a", "sd:/ultimate/mods/b"]);
+        }
+
+        #[test]
+        fn unranked_mods_come_last_by_path() {
+            let sorted = sort(
+                &["sd:/ultimate/mods/d", "sd:/ultimate/mods/b", "sd:/ultimate/mods/c", "sd:/ultimate/mods/a"],
+                &["sd:/ultimate/mods/c"],
+                &["sd:/ultimate/mods"],
+            );
+
+            assert_eq!(
+                sorted,
+                ["sd:/ultimate/mods/c", "sd:/ultimate/mods/a", "sd:/ultimate/mods/b", "sd:/ultimate/mods/d"]
+            );
+        }
+
+        #[test]
+        fn the_mod_folder_comes_before_the_workspace() {
+            let sorted = sort(
+                &["sd:/ultimate/mods/a", "sd:/ultimate/skins/b", "sd:/ultimate/mods/c"],
+                &["sd:/ultimate/mods/a", "sd:/ultimate/mods/c", "sd:/ultimate/skins/b"],
+                &["sd:/ultimate/skins", "sd:/ultimate/mods"],
+            );
+
+            assert_eq!(sorted, ["sd:/ultimate/skins/b", "sd:/ultimate/mods/a", "sd:/ultimate/mods/c"]);
+        }
+
+        #[test]
+        fn unranked_mods_follow_their_declared_priority() {
+            let folder = std::env::temp_dir().join("arcropolis_test_declared_priority");
+            let low = folder.join("low");
+            let high = folder.join("high");
+
+            for (root, priority) in [(&low, -1), (&high, 5)].iter() {
+                std::fs::create_dir_all(root).unwrap();
+                std::fs::write(root.join("info.toml"), format!("priority = {}\n", priority)).unwrap();
+            }
+
+            let plain = folder.join("plain");
+            let folder = folder.to_string_lossy().into_owned();
+            let paths: Vec<String> = [&low, &plain, &high].iter().map(|root| root.to_string_lossy().into_owned()).collect();
+            let mods: Vec<&str> = paths.iter().map(String::as_str).collect();
+
+            let sorted = sort(&mods, &[], &[folder.as_str()]);
+
+            assert_eq!(sorted, [paths[2].clone(), paths[1].clone(), paths[0].clone()]);
+        }
+    }
+}
+
 pub struct ArcStorage(std::path::PathBuf);
 
 impl ArcStorage {
@@ -338,3 +553,50 @@ impl ConfigStorage for ArcStorage {
         self.root_path().join(&self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(path: &str, priority: i32) -> ModFolder {
+        ModFolder {
+            path: path.to_string(),
+            priority,
+        }
+    }
+
+    fn paths(folders: &[ModFolder]) -> Vec<&str> {
+        folders.iter().map(|folder| folder.path.as_str()).collect()
+    }
+
+    #[test]
+    fn sorts_mod_folders_from_the_highest_priority() {
+        let folders = normalize_mod_folders(vec![
+            folder("sd:/ultimate/mods", 0),
+            folder("sd:/ultimate/skins", 10),
+            folder("sd:/ultimate/music", -5),
+        ]);
+
+        assert_eq!(paths(&folders), ["sd:/ultimate/skins", "sd:/ultimate/mods", "sd:/ultimate/music"]);
+    }
+
+    #[test]
+    fn trims_mod_folders_and_drops_empty_ones() {
+        let folders = normalize_mod_folders(vec![folder(" sd:/ultimate/mods/ ", 0), folder("  ", 1), folder("/", 2)]);
+
+        assert_eq!(paths(&folders), ["sd:/ultimate/mods"]);
+    }
+
+    #[test]
+    fn keeps_the_highest_priority_of_overlapping_mod_folders() {
+        let folders = normalize_mod_folders(vec![
+            folder("sd:/ultimate/mods", 0),
+            folder("sd:/ultimate/mods/skins", 5),
+            folder("sd:/ultimate/mods/", -1),
+            folder("sd:/ultimate/mods2", 1),
+        ]);
+
+        // sd:/ultimate/mods2 is not inside of sd:/ultimate/mods, only its name starts the same way
+        assert_eq!(paths(&folders), ["sd:/ultimate/mods/skins", "sd:/ultimate/mods2"]);
+    }
+}
@@ -9,7 +9,7 @@ use std::{
 };
 
 use arc_config::{Config as ModConfig, ToExternal, ToSmashArc};
-use orbits::{orbit::LaunchPad, Error, FileEntryType, FileLoader, Orbit, StandardLoader, Tree};
+use orbits::{Error, FileEntryType, FileLoader, Orbit, StandardLoader, Tree};
 use owo_colors::OwoColorize;
 use smash_arc::{ArcLookup, Hash40, LoadedArc, LoadedSearchSection, LookupError, SearchLookup};
 use thiserror::Error;
@@ -22,14 +22,22 @@ use crate::{
     resource, PathExtension,
 };
 
+mod cache;
+pub mod conflicts;
 mod discover;
 mod utils;
 pub use discover::*;
 pub mod loaders;
 pub use loaders::*;
+pub mod patches;
+use patches::{PatchFile, PatchHandler};
+pub mod source;
+pub use source::FileSource;
 
 static DEFAULT_CONFIG: &str = include_str!("../resources/override.json");
 static IS_INIT: AtomicBool = AtomicBool::new(false);
+// Files that are not in the data.arc can only be added until the mods are processed
+static MODS_PROCESSED: AtomicBool = AtomicBool::new(false);
 // pub type ApiLoader = StandardLoader; // temporary until an actual ApiLoader is implemented
 
 pub type ArcropolisOrbit = Orbit<ArcLoader, StandardLoader, ApiLoader>;
@@ -46,7 +54,10 @@ pub struct CachedFilesystem {
     loader: ArcropolisOrbit,
     config: ModConfig,
     hash_lookup: HashMap<Hash40, PathBuf>,
+    file_sources: HashMap<Hash40, FileSource>,
     hash_size_cache: HashMap<Hash40, usize>,
+    // The size every patched FileData had in the data.arc, before it was first patched
+    vanilla_sizes: HashMap<Hash40, usize>,
     incoming_load: Option<Hash40>,
     bytes_remaining: usize,
     current_nus3bank_id: u32,
@@ -56,8 +67,8 @@ pub struct CachedFilesystem {
 
 impl CachedFilesystem {
     /// Load all configs that were found during discovery and join them into a singular config
-    fn load_remaining_configs(current: &mut ModConfig, launchpad: &LaunchPad<StandardLoader>) {
-        for (root, local) in launchpad.collected_paths().iter() {
+    fn load_remaining_configs(current: &mut ModConfig, collected: &[(PathBuf, PathBuf)]) {
+        for (root, local) in collected.iter() {
             let full_path = root.join(local);
             if !full_path.exists() {
                 warn!("Collected path at {} does not exist.", full_path.display());
@@ -80,75 +91,16 @@ impl CachedFilesystem {
         }
     }
 
-    /// Get a list of all PRC patch files and add them to the virtual tree
-    fn initialize_prc_patches(launchpad: &LaunchPad<StandardLoader>, api_tree: &mut Tree<ApiLoader>) -> HashSet<Hash40> {
+    /// Get a list of all patch files, of every known format, and add them to the virtual tree
+    fn initialize_patches(discovery: &Discovery, api_tree: &mut Tree<ApiLoader>, file_sources: &mut HashMap<Hash40, FileSource>) -> HashSet<Hash40> {
         let mut set = HashSet::new();
-        for (root, path) in launchpad.collected_paths().iter() {
-            // The collected paths gives us everything so we only want these extensions
-            if path.has_extension("prcx")
-                || path.has_extension("prcxml")
-                || path.has_extension("stdatx")
-                || path.has_extension("stdatxml")
-                || path.has_extension("stprmx")
-                || path.has_extension("stprmxml")
-            {
-                if let Some(hash) = utils::add_prc_patch(api_tree, root, path) {
-                    set.insert(hash);
-                }
-            }
-        }
-        set
-    }
-
-    /// Get a list of all MSBT patch files and add them to the virtual tree
-    fn initialize_msbt_patches(launchpad: &LaunchPad<StandardLoader>, api_tree: &mut Tree<ApiLoader>) -> HashSet<Hash40> {
-        let mut set = HashSet::new();
-        for (root, path) in launchpad.collected_paths().iter() {
-            // The collected paths gives us everything so we only want these extensions
-            if path.has_extension("xmsbt") {
-                if let Some(hash) = utils::add_msbt_patch(api_tree, root, path) {
-                    set.insert(hash);
-                }
-            }
-        }
-        set
-    }
-
-    /// Get a list of all nus3audio patch files and add them to the virtual tree
-    fn initialize_nus3audio_patches(launchpad: &LaunchPad<StandardLoader>, api_tree: &mut Tree<ApiLoader>) -> HashSet<Hash40> {
-        let mut set = HashSet::new();
-        for (root, path) in launchpad.collected_paths().iter() {
-            // The collected paths gives us everything so we only want these extensions
-            if path.has_extension("patch3audio") {
-                if let Some(hash) = utils::add_nus3audio_patch(api_tree, root, path) {
-                    set.insert(hash);
-                }
-            }
-        }
-        set
-    }
-
-    /// Get a list of all motion list patch files and add them to the virtual tree
-    fn initialize_motionlist_patches(launchpad: &LaunchPad<StandardLoader>, api_tree: &mut Tree<ApiLoader>) -> HashSet<Hash40> {
-        let mut set = HashSet::new();
-        for (root, path) in launchpad.collected_paths().iter() {
-            // The collected paths gives us everything so we only want these extensions
-            if path.has_extension("motdiff") || path.ends_with("motion_list.yml") {
-                if let Some(hash) = utils::add_motionlist_patch(api_tree, root, path) {
-                    set.insert(hash);
-                }
-            }
-        }
-        set
-    }
-
-    /// Get a list of all bgm_property files and add them to the virtual tree
-    fn initialize_bgm_property_patches(launchpad: &LaunchPad<StandardLoader>, api_tree: &mut Tree<ApiLoader>) -> HashSet<Hash40> {
-        let mut set = HashSet::new();
-        for (root, path) in launchpad.collected_paths().iter() {
-            // The collected paths gives us everything so we only want these extensions
-            if path.file_name() == Path::new("bgm_property.bin").file_name() {
-                if let Some(hash) = utils::add_bgm_property_patch(api_tree, root, path) {
+        let uncollected = utils::get_uncollected_patch_files(&discovery.files);
+        for (root, path) in discovery.collected.iter().chain(uncollected.iter()) {
+            // The collected paths gives us everything so we only want the ones a patch format claims
+            if let Some(handler) = patches::handler_for_file(path) {
+                if let Some(hash) = utils::add_patch_file(api_tree, handler.as_ref(), root, path) {
+                    let source = file_sources.remove(&hash).unwrap_or(FileSource::Vanilla);
+                    file_sources.insert(hash, source.with_patch(handler.name(), PatchFile::new(root, &root.join(path))));
                     set.insert(hash);
                 }
             }
@@ -164,32 +116,90 @@ impl CachedFilesystem {
         match pending {
             PendingApiCall::GenericCallback { hash, max_size, callback } => {
                 let path = get_path_from_hash(hash);
+                let callback = ApiCallback::GenericCallback(callback);
+                let kind = callback.kind();
 
-                utils::add_file_to_api_tree(api_tree, "api:/generic-cb", &path, ApiCallback::GenericCallback(callback));
+                utils::add_file_to_api_tree(api_tree, "api:/generic-cb", &path, callback);
 
                 ApiCallResult {
                     hash,
                     path,
                     size: Some(max_size),
+                    callback: kind,
                 }
             },
             PendingApiCall::StreamCallback { hash, callback } => {
                 let path = get_path_from_hash(hash);
+                let callback = ApiCallback::StreamCallback(callback);
+                let kind = callback.kind();
 
-                utils::add_file_to_api_tree(api_tree, "api:/stream-cb", &path, ApiCallback::StreamCallback(callback));
+                utils::add_file_to_api_tree(api_tree, "api:/stream-cb", &path, callback);
 
-                ApiCallResult { hash, path, size: None }
+                ApiCallResult {
+                    hash,
+                    path,
+                    size: None,
+                    callback: kind,
+                }
+            },
+            PendingApiCall::StreamDataCallback { hash, size, callback } => {
+                let path = get_path_from_hash(hash);
+                let callback = ApiCallback::StreamDataCallback(callback, size);
+                let kind = callback.kind();
+
+                utils::add_file_to_api_tree(api_tree, "api:/stream-data-cb", &path, callback);
+
+                ApiCallResult {
+                    hash,
+                    path,
+                    size: None,
+                    callback: kind,
+                }
+            },
+            PendingApiCall::SizedCallback { hash, size_callback, callback } => {
+                let path = get_path_from_hash(hash);
+                let callback = ApiCallback::GenericCallback(callback);
+                let kind = callback.kind();
+
+                utils::add_file_to_api_tree(api_tree, "api:/generic-cb", &path, callback);
+                api_tree.loader.set_size_callback(hash, size_callback);
+
+                // The size is only known right before the file is loaded, see CachedFilesystem::negotiate_size
+                ApiCallResult {
+                    hash,
+                    path,
+                    size: None,
+                    callback: kind,
+                }
+            },
+            PendingApiCall::VirtualFile { hash, data } => {
+                let path = get_path_from_hash(hash);
+                let size = data.len();
+
+                // Adding the same file again only swaps the data, so that the previous data is not kept around
+                if !api_tree.loader.replace_virtual_file(hash, &data) {
+                    utils::add_file_to_api_tree(api_tree, "api:/virtual-file", &path, ApiCallback::VirtualFile(data));
+                }
+
+                ApiCallResult {
+                    hash,
+                    path,
+                    size: Some(size),
+                    callback: None,
+                }
             },
         }
     }
 
     /// Use the file information that was generated during file discovery to fill out a GlobalFilesystem struct
-    fn make_from_promise(launchpad: LaunchPad<StandardLoader>) -> CachedFilesystem {
+    fn make_from_promise(discovery: Discovery) -> CachedFilesystem {
         let arc = resource::arc();
         // Provide the discovered tree and get two hashmaps, one of the sizes of each file discovered (for patching)
         // and also get hash40 -> PathBuf lookup, since it's going to be a lot faster when the game is loading
         // individual files
-        let (mut hashed_sizes, mut hashed_paths) = utils::make_hash_maps(launchpad.tree());
+        // The sources of the files are tracked along the way, so that we can tell where every file comes from
+        // The sizes and hashes of the roots that did not change since the last boot come from the discovery cache
+        let (mut hashed_sizes, mut hashed_paths, mut file_sources) = utils::make_hash_maps(&discovery.files);
 
         // Add the discovered paths to the global hashes, so that when a file is loading that *we have discovered* we can guarantee
         // that we are printing the real path in the logger.
@@ -209,22 +219,18 @@ impl CachedFilesystem {
         };
 
         // Load all of the user configs into the main config
-        Self::load_remaining_configs(&mut config, &launchpad);
+        Self::load_remaining_configs(&mut config, &discovery.collected);
 
         // Collect all of the NUS3BANK dependencies that audio files have in order to be unshared
         // Note that we pass the unshare blacklist because if the NUS3AUDIO files are blacklisted then we shouldn't unshare the
         // actual nus3bank either
-        let nus3audio_deps = utils::get_required_nus3banks(launchpad.tree(), &config.unshare_blacklist);
+        let nus3audio_deps = utils::get_required_nus3banks(discovery.launchpad.tree(), &config.unshare_blacklist);
 
         // Create the API file tree and start adding things to it
         let mut api_tree = Tree::new(ApiLoader::default());
 
         // Set up the API tree with all of the patch files
-        let mut hashes = Self::initialize_prc_patches(&launchpad, &mut api_tree);
-        hashes.extend(Self::initialize_msbt_patches(&launchpad, &mut api_tree));
-        hashes.extend(Self::initialize_nus3audio_patches(&launchpad, &mut api_tree));
-        hashes.extend(Self::initialize_motionlist_patches(&launchpad, &mut api_tree));
-        hashes.extend(Self::initialize_bgm_property_patches(&launchpad, &mut api_tree));
+        let hashes = Self::initialize_patches(&discovery, &mut api_tree, &mut file_sources);
 
         // Add the hash files and set the new size to 10x the original files
         for hash in hashes {
@@ -235,9 +241,11 @@ impl CachedFilesystem {
         }
 
         // Add all of the NUS3BANKs that our NUS3AUDIOs depend on to the API tree
+        let nus3bank_root = patches::nus3bank::Nus3bankHandler.api_root();
         for dep in nus3audio_deps {
-            let hash = utils::add_file_to_api_tree(&mut api_tree, "api:/patch-nus3bank", &dep, ApiCallback::None);
+            let hash = utils::add_file_to_api_tree(&mut api_tree, &nus3bank_root, &dep, ApiCallback::None);
             if let Some(hash) = hash {
+                file_sources.insert(hash, FileSource::Vanilla);
                 hashed_paths.insert(hash, dep);
                 hashed_sizes.insert(hash, 0); // We want to use vanilla size because we are only editing the content
             }
@@ -251,8 +259,11 @@ impl CachedFilesystem {
 
         // Go through each API call, insert it into the api tree, and then insert it's info into the global data
         for call in calls {
-            let ApiCallResult { hash, path, size } = Self::handle_panding_api_call(&mut api_tree, call);
+            let result = Self::handle_panding_api_call(&mut api_tree, call);
+            let source = result.source(file_sources.remove(&result.hash));
+            let ApiCallResult { hash, path, size, .. } = result;
 
+            file_sources.insert(hash, source);
             hashed_paths.insert(hash, path);
             if let Some(size) = size {
                 hashed_sizes.insert(hash, size);
@@ -264,10 +275,12 @@ impl CachedFilesystem {
 
         // Construct a CachedFilesystem
         CachedFilesystem {
-            loader: launchpad.launch(ArcLoader(arc), api_tree),
+            loader: discovery.launchpad.launch(ArcLoader(arc), api_tree),
             config,
             hash_lookup: hashed_paths,
+            file_sources,
             hash_size_cache: hashed_sizes,
+            vanilla_sizes: HashMap::new(),
             incoming_load: None,
             bytes_remaining: 0,
             current_nus3bank_id: 7420,
@@ -277,7 +290,7 @@ impl CachedFilesystem {
     }
 
     /// Patches a file in the LoadedArc
-    fn patch_file(&self, hash: Hash40, size: usize) -> Option<usize> {
+    fn patch_file(&mut self, hash: Hash40, size: usize) -> Option<usize> {
         let arc = resource::arc_mut();
         let region = config::region();
         let decomp_size = match arc.get_file_data_from_hash(hash, region) {
@@ -296,6 +309,7 @@ impl CachedFilesystem {
         if size > decomp_size {
             match arc.patch_filedata(hash, size as u32, region) {
                 Ok(old_size) => {
+                    self.vanilla_sizes.entry(hash).or_insert(old_size as usize);
                     // info!(
                     //     "File '{}' ({:#x}) has a new decompressed filesize! {:#x} -> {:#x}",
                     //     hashes::find(hash).bright_yellow(),
@@ -383,10 +397,29 @@ impl CachedFilesystem {
             );
         }
         self.incoming_load = hash;
+        self.bytes_remaining = 0;
+
         if let Some(hash) = hash {
-            self.bytes_remaining = *self.hash_size_cache.get(&hash).unwrap_or(&0);
-        } else {
-            self.bytes_remaining = 0;
+            // Files only queued for their extension callbacks are not in the cache, and are loaded with their vanilla size
+            let size = self.hash_size_cache.get(&hash).copied().or_else(|| {
+                resource::arc()
+                    .get_file_data_from_hash(hash, config::region())
+                    .ok()
+                    .map(|data| data.decomp_size as usize)
+            });
+
+            match size {
+                Some(size) => self.bytes_remaining = size,
+                // Without a size, the file would be handed over after its first chunk instead of once the game read all of it
+                None => {
+                    warn!(
+                        "Cannot queue file '{}' ({:#x}) because its size is unknown.",
+                        hashes::find(hash),
+                        hash.0
+                    );
+                    self.incoming_load = None;
+                },
+            }
         }
     }
 
@@ -426,18 +459,16 @@ impl CachedFilesystem {
     pub fn reshare_files(&mut self) {
         let arc = resource::arc();
         let file_paths = arc.get_file_paths();
+        let shared_hash = |hash: Hash40| {
+            arc.get_file_info_from_hash(hash)
+                .map_or_else(|_| hash, |info| file_paths[info.file_path_index].path.hash40())
+        };
         let mut old_map = HashMap::new();
         std::mem::swap(&mut self.hash_lookup, &mut old_map);
-        self.hash_lookup = old_map
-            .into_iter()
-            .map(|(hash, path)| {
-                (
-                    arc.get_file_info_from_hash(hash)
-                        .map_or_else(|_| hash, |info| file_paths[info.file_path_index].path.hash40()),
-                    path,
-                )
-            })
-            .collect();
+        self.hash_lookup = old_map.into_iter().map(|(hash, path)| (shared_hash(hash), path)).collect();
+        let mut old_sources = HashMap::new();
+        std::mem::swap(&mut self.file_sources, &mut old_sources);
+        self.file_sources = old_sources.into_iter().map(|(hash, source)| (shared_hash(hash), source)).collect();
     }
 
     /// Goes through and performs the required file manipulation in order to load mods
@@ -469,7 +500,7 @@ impl CachedFilesystem {
 
         // Go through and add any files that were not found in the data.arc
         self.loader.walk_patch(|node, ty| {
-            if node.get_local().is_stream() || !ty.is_file() {
+            if node.get_local().is_stream() || !ty.is_file() || patches::handler_for_file(node.get_local()).is_some() {
                 return;
             }
 
@@ -486,6 +517,21 @@ impl CachedFilesystem {
             replacement::addition::add_searchable_file_recursive(&mut search_context, node.get_local());
         });
 
+        // Virtual files from plugins can be new files too
+        self.loader.virt().walk_paths(|node, ty| {
+            if !ty.is_file() || !node.full_path().starts_with("api:/virtual-file") {
+                return;
+            }
+
+            match node.get_local().smash_hash() {
+                Ok(hash) if !context.contains_file(hash) => {
+                    replacement::addition::add_file(&mut context, node.get_local());
+                    replacement::addition::add_searchable_file_recursive(&mut search_context, node.get_local());
+                },
+                _ => {},
+            }
+        });
+
         // Don't unshare any files in the unshare blacklist (nus3audio handled during filesystem finish)
         let files = self.hash_lookup.iter().filter_map(
             |(hash, _path)| {
@@ -533,6 +579,8 @@ impl CachedFilesystem {
 
         resource::arc_mut().take_context(context);
         resource::search_mut().take_context(search_context);
+
+        MODS_PROCESSED.store(true, Ordering::SeqCst);
     }
 
     /// Gets the global mod config
@@ -542,27 +590,145 @@ impl CachedFilesystem {
 
     /// Handles late API calls
     pub fn handle_late_api_call(&mut self, call: api::PendingApiCall) {
-        let ApiCallResult { hash, path, size } = Self::handle_panding_api_call(self.loader.virt_mut(), call);
+        let result = Self::handle_panding_api_call(self.loader.virt_mut(), call);
+        let source = result.source(self.file_sources.remove(&result.hash));
+        let ApiCallResult { hash, path, size, .. } = result;
 
+        self.file_sources.insert(hash, source);
         self.hash_lookup.insert(hash, path);
         if let Some(size) = size {
-            if let Some(old_size) = self.patch_file(hash, size) {
-                if let Some(size_mut) = self.hash_size_cache.get_mut(&hash) {
-                    if *size_mut > old_size {
-                        *size_mut = old_size;
-                    }
-                } else {
-                    self.hash_size_cache.insert(hash, size);
+            self.update_size(hash, size);
+        }
+    }
+
+    /// Patches the filesize of a file for a late API call, keeping the size cache in sync
+    fn update_size(&mut self, hash: Hash40, size: usize) {
+        // New files are only added to the data.arc when processing the mods, and get their size along with the files of mods
+        if !GlobalFilesystem::are_mods_processed() && resource::arc().get_file_path_index_from_hash(hash).is_err() {
+            self.hash_size_cache.insert(hash, size);
+            return;
+        }
+
+        if let Some(old_size) = self.patch_file(hash, size) {
+            if let Some(size_mut) = self.hash_size_cache.get_mut(&hash) {
+                if *size_mut > old_size {
+                    *size_mut = old_size;
                 }
+            } else {
+                self.hash_size_cache.insert(hash, size);
             }
         }
     }
 
+    pub fn has_size_callbacks(&self) -> bool {
+        self.loader.virt().loader.has_size_callbacks()
+    }
+
+    /// Asks the plugin for the exact size of a file right before the game allocates its buffer, and patches the FileData to match
+    pub fn negotiate_size(&mut self, hash: Hash40) {
+        let size_callback = match self.loader.virt().loader.get_size_callback(hash) {
+            Some(size_callback) => size_callback,
+            None => return,
+        };
+
+        let mut size = 0;
+
+        if !size_callback(hash.0, &mut size) {
+            warn!(
+                "Size callback for '{}' ({:#x}) did not provide a size, keeping the current one.",
+                hashes::find(hash),
+                hash.0
+            );
+            return;
+        }
+
+        match resource::arc_mut().patch_filedata(hash, size as u32, config::region()) {
+            Ok(old_size) => {
+                let vanilla_size = *self.vanilla_sizes.entry(hash).or_insert(old_size as usize);
+
+                // Like in update_size, the game reads at most the data.arc's data before we replace it, so the remaining bytes are
+                // counted against the smallest of the negotiated size and the vanilla size
+                self.hash_size_cache.insert(hash, size.min(vanilla_size));
+            },
+            Err(_) => warn!(
+                "Failed to patch '{}' ({:#x}) filesize! It should be {:#x}.",
+                hashes::find(hash).bright_yellow(),
+                hash.0,
+                size.green()
+            ),
+        }
+    }
+
+    /// Removes a plugin callback from the chain of a file. Once nothing replaces the file anymore, it is loaded from the data.arc again.
+    pub fn remove_api_callback(&mut self, hash: Hash40, address: usize) -> bool {
+        let callback = match self.loader.virt_mut().loader.remove_callback(hash, address) {
+            Some(callback) => callback,
+            None => return false,
+        };
+
+        let source = match (self.file_sources.remove(&hash), callback.kind()) {
+            (Some(source), Some(kind)) => source.without_callback(kind),
+            (source, _) => source,
+        };
+
+        match source {
+            Some(source) => {
+                self.file_sources.insert(hash, source);
+            },
+            None => {
+                self.hash_lookup.remove(&hash);
+                self.hash_size_cache.remove(&hash);
+            },
+        }
+
+        true
+    }
+
+    /// Swaps a plugin callback of a file for a new one of the same kind, patching the filesize if the new one needs more space
+    pub fn replace_api_callback(&mut self, hash: Hash40, address: usize, callback: ApiCallback, size: Option<usize>) -> bool {
+        if !self.loader.virt_mut().loader.replace_callback(hash, address, callback) {
+            return false;
+        }
+
+        if let Some(size) = size {
+            self.update_size(hash, size);
+        }
+
+        true
+    }
+
     /// Gets the cached size
     pub fn get_cached_size(&self, hash: Hash40) -> Option<usize> {
         self.hash_size_cache.get(&hash).copied()
     }
 
+    /// Gets where the data of a file comes from
+    pub fn get_file_source(&self, hash: Hash40) -> Option<&FileSource> {
+        self.file_sources.get(&hash)
+    }
+
+    /// Writes the source of every file in the filesystem, sorted by path
+    pub fn dump_file_sources<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        let mut sources: Vec<_> = self
+            .file_sources
+            .iter()
+            .map(|(hash, source)| {
+                let path = match self.hash_lookup.get(hash) {
+                    Some(path) => path.display().to_string(),
+                    None => hashes::find(*hash).to_string(),
+                };
+                (path, source)
+            })
+            .collect();
+        sources.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        for (path, source) in sources {
+            writeln!(output, "{}: {}", path, source)?;
+        }
+
+        Ok(())
+    }
+
     pub fn get_sum_size(&self) -> usize {
         self.total_size
     }
@@ -570,7 +736,7 @@ impl CachedFilesystem {
 
 pub enum GlobalFilesystem {
     Uninitialized,
-    Promised(std::thread::JoinHandle<LaunchPad<StandardLoader>>),
+    Promised(std::thread::JoinHandle<Discovery>),
     Initialized(Box<CachedFilesystem>),
 }
 
@@ -578,6 +744,18 @@ struct ApiCallResult {
     hash: Hash40,
     path: PathBuf,
     size: Option<usize>,
+    /// The kind of callback that provides the file, or None for virtual files
+    callback: Option<&'static str>,
+}
+
+impl ApiCallResult {
+    /// Gets the source of the file once the API call is handled, out of the source it had before
+    fn source(&self, previous: Option<FileSource>) -> FileSource {
+        match self.callback {
+            Some(kind) => FileSource::callback(kind, previous),
+            None => FileSource::Virtual,
+        }
+    }
 }
 
 impl GlobalFilesystem {
@@ -585,7 +763,7 @@ impl GlobalFilesystem {
         match self {
             Self::Uninitialized => Err(FilesystemUninitializedError),
             Self::Promised(promise) => match promise.join() {
-                Ok(launchpad) => Ok(Self::Initialized(Box::new(CachedFilesystem::make_from_promise(launchpad)))),
+                Ok(discovery) => Ok(Self::Initialized(Box::new(CachedFilesystem::make_from_promise(discovery)))),
                 Err(_) => Err(FilesystemUninitializedError),
             },
             Self::Initialized(filesystem) => Ok(Self::Initialized(filesystem)),
@@ -596,6 +774,11 @@ impl GlobalFilesystem {
         IS_INIT.load(Ordering::SeqCst)
     }
 
+    /// Checks if the files that are not in the data.arc were already added to it
+    pub fn are_mods_processed() -> bool {
+        MODS_PROCESSED.load(Ordering::SeqCst)
+    }
+
     pub fn take(&mut self) -> Self {
         let mut out = GlobalFilesystem::Uninitialized;
         std::mem::swap(self, &mut out);
@@ -741,6 +924,33 @@ impl GlobalFilesystem {
         }
     }
 
+    pub fn has_size_callbacks(&self) -> bool {
+        match self {
+            Self::Initialized(fs) => fs.has_size_callbacks(),
+            _ => false,
+        }
+    }
+
+    pub fn negotiate_size(&mut self, hash: Hash40) {
+        if let Self::Initialized(fs) = self {
+            fs.negotiate_size(hash)
+        }
+    }
+
+    pub fn remove_api_callback(&mut self, hash: Hash40, address: usize) -> bool {
+        match self {
+            Self::Initialized(fs) => fs.remove_api_callback(hash, address),
+            _ => false,
+        }
+    }
+
+    pub fn replace_api_callback(&mut self, hash: Hash40, address: usize, callback: ApiCallback, size: Option<usize>) -> bool {
+        match self {
+            Self::Initialized(fs) => fs.replace_api_callback(hash, address, callback, size),
+            _ => false,
+        }
+    }
+
     pub fn get_cached_size(&self, hash: Hash40) -> Option<usize> {
         match self {
             Self::Initialized(fs) => fs.get_cached_size(hash),
@@ -754,4 +964,18 @@ impl GlobalFilesystem {
             _ => None,
         }
     }
+
+    pub fn get_file_source(&self, hash: Hash40) -> Option<&FileSource> {
+        match self {
+            Self::Initialized(fs) => fs.get_file_source(hash),
+            _ => None,
+        }
+    }
+
+    pub fn dump_file_sources<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        match self {
+            Self::Initialized(fs) => fs.dump_file_sources(output),
+            _ => Ok(()),
+        }
+    }
 }
@@ -0,0 +1,161 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use smash_arc::Hash40;
+
+// Bump whenever the layout of the cache changes, so that old caches are thrown away instead of misread
+const CACHE_VERSION: u32 = 3;
+
+/// A file of a mod root that goes in the discovered tree
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveredFile {
+    pub local: PathBuf,
+    pub size: usize,
+    hash: u64,
+}
+
+impl DiscoveredFile {
+    pub fn new(local: PathBuf, size: usize, hash: Hash40) -> Self {
+        Self { local, size, hash: hash.0 }
+    }
+
+    pub fn hash(&self) -> Hash40 {
+        Hash40(self.hash)
+    }
+}
+
+/// Everything discovery found in a mod root, and the modification time of every directory it was found in
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DiscoveredRoot {
+    directories: HashMap<PathBuf, u64>,
+    /// The files that go in the tree
+    pub files: Vec<DiscoveredFile>,
+    /// The local paths of the files that were collected instead, like plugins, configs and patch files
+    pub collected: Vec<PathBuf>,
+}
+
+impl DiscoveredRoot {
+    /// Remembers the modification time of a directory of the root, the root itself being an empty local path
+    pub fn add_directory(&mut self, root: &Path, local: &Path) {
+        if let Some(mtime) = modification_time(&root.join(local)) {
+            self.directories.insert(local.to_path_buf(), mtime);
+        }
+    }
+
+    /// Adding, removing or renaming anything in a directory changes its modification time.
+    /// Overwriting a file in place does not, so a file that changes size has to be replaced rather than edited.
+    fn is_up_to_date(&self, root: &Path) -> bool {
+        !self.directories.is_empty()
+            && self
+                .directories
+                .iter()
+                .all(|(local, mtime)| modification_time(&root.join(local)) == Some(*mtime))
+    }
+}
+
+fn modification_time(path: &Path) -> Option<u64> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheData {
+    version: u32,
+    /// Files of other regions are ignored during discovery, so changing it changes what every root provides
+    region: String,
+    /// The enabled mod roots, sorted. If the preset enables other mods, the whole cache is discarded.
+    enabled: Vec<PathBuf>,
+    roots: HashMap<PathBuf, DiscoveredRoot>,
+}
+
+/// The mod roots discovered during the previous boot, reused for the roots that did not change since then
+pub struct DiscoveryCache {
+    previous: HashMap<PathBuf, DiscoveredRoot>,
+    current: CacheData,
+    hits: usize,
+    misses: usize,
+}
+
+impl DiscoveryCache {
+    fn path() -> PathBuf {
+        crate::utils::paths::cache().join("discovery.bin").into_std_path_buf()
+    }
+
+    /// Reads the cache of the previous boot, if it was made for the same enabled mod roots and region
+    pub fn load(roots: &[PathBuf]) -> Self {
+        let mut enabled = roots.to_vec();
+        enabled.sort();
+
+        let region = crate::config::region().to_string();
+
+        let previous = match std::fs::read(Self::path()) {
+            Ok(data) => match bincode::deserialize::<CacheData>(&data) {
+                Ok(cache) if cache.version == CACHE_VERSION && cache.region == region && cache.enabled == enabled => cache.roots,
+                Ok(_) => {
+                    info!("Discovery cache is outdated, the enabled mods, the region or the cache format changed.");
+                    HashMap::new()
+                },
+                Err(e) => {
+                    error!("Unable to parse '{}'. Reason: {:?}", Self::path().display(), *e);
+                    HashMap::new()
+                },
+            },
+            Err(_) => HashMap::new(),
+        };
+
+        Self {
+            previous,
+            current: CacheData {
+                version: CACHE_VERSION,
+                region,
+                enabled,
+                roots: HashMap::new(),
+            },
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Takes what was discovered in a root during the previous boot, if none of its directories changed since then
+    pub fn take(&mut self, root: &Path) -> Option<DiscoveredRoot> {
+        let cached = self.previous.remove(root).filter(|cached| cached.is_up_to_date(root));
+
+        if cached.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+
+        cached
+    }
+
+    /// Remembers what was discovered in a root for the next boot
+    pub fn insert(&mut self, root: &Path, discovered: DiscoveredRoot) {
+        self.current.roots.insert(root.to_path_buf(), discovered);
+    }
+
+    /// Writes the cache for the next boot, unless every root was reused as it is
+    pub fn save(&self) {
+        info!("Discovery cache: {} mod roots reused, {} mod roots walked again.", self.hits, self.misses);
+
+        if self.misses == 0 && self.previous.is_empty() && Self::path().exists() {
+            return;
+        }
+
+        match bincode::serialize(&self.current) {
+            Ok(data) => {
+                if let Err(e) = std::fs::write(Self::path(), data) {
+                    error!("Failed to write discovery cache to '{}'. Reason: {:?}", Self::path().display(), e);
+                }
+            },
+            Err(e) => error!("Failed to serialize the discovery cache. Reason: {:?}", *e),
+        }
+    }
+}
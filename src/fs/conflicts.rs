@@ -0,0 +1,68 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use thiserror::Error;
+
+pub static CONFLICTS_PATH: &str = "sd:/ultimate/arcropolis/conflicts.json";
+pub static PRIORITY_DECISIONS_PATH: &str = "sd:/ultimate/arcropolis/priority_decisions.json";
+
+// Every conflict reported during this run, so that a file patched later while the game is loading does not erase the conflicts of the ones before it
+static CONFLICT_MAP: Lazy<Mutex<HashMap<PathBuf, Vec<PathBuf>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Error)]
+pub enum ConflictFileError {
+    #[error("Failed to serialize conflict map to JSON. {0:?}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("Failed to write conflict map to sd:/ultimate/arcropolis/conflicts.json<br>{0:?}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to write priority decisions to sd:/ultimate/arcropolis/priority_decisions.json<br>{0:?}")]
+    DecisionsIo(std::io::Error),
+}
+
+/// Which mod root provides a file that several enabled mods contain, as decided by the mod priority of the workspace
+#[derive(Debug, Serialize)]
+pub struct PriorityDecision {
+    pub winner: PathBuf,
+    pub overridden: Vec<PathBuf>,
+}
+
+/// Adds conflicts to the ones known for this run and rewrites the conflict file if any of them is new.
+/// The keys are the conflicting files (or entries in those files), and the values are the mod roots that provide them.
+pub fn extend(conflicts: &HashMap<PathBuf, Vec<PathBuf>>) -> Result<(), ConflictFileError> {
+    let mut map = CONFLICT_MAP.lock();
+    let mut changed = false;
+
+    for (local, roots) in conflicts.iter() {
+        let known_roots = map.entry(local.clone()).or_default();
+
+        for root in roots.iter() {
+            if !known_roots.contains(root) {
+                known_roots.push(root.clone());
+                changed = true;
+            }
+        }
+    }
+
+    // Files like MSBTs are patched every time the game loads them, and usually report the same conflicts again
+    if !changed {
+        return Ok(());
+    }
+
+    let json = serde_json::to_string_pretty(&*map)?;
+    std::fs::write(CONFLICTS_PATH, json.as_bytes())?;
+
+    Ok(())
+}
+
+/// Writes every file conflict resolved by mod priority during discovery, replacing the decisions of the previous run
+pub fn write_priority_decisions(decisions: &BTreeMap<PathBuf, PriorityDecision>) -> Result<(), ConflictFileError> {
+    let json = serde_json::to_string_pretty(decisions)?;
+    std::fs::write(PRIORITY_DECISIONS_PATH, json.as_bytes()).map_err(ConflictFileError::DecisionsIo)
+}
@@ -1,24 +1,40 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     path::{Path, PathBuf},
 };
 
 use camino::Utf8Path;
-use orbits::{ConflictHandler, ConflictKind, FileLoader, LaunchPad, StandardLoader, Tree};
+use orbits::{ConflictHandler, FileLoader, LaunchPad, StandardLoader, Tree};
 use skyline::nn::{self, ro::*};
 use smash_arc::Hash40;
 
-use crate::{chainloader::*, config, utils};
+use self::manifest::{Plugin, RejectReason};
+use super::{
+    cache::{DiscoveredFile, DiscoveredRoot, DiscoveryCache},
+    conflicts::{self, PriorityDecision},
+    patches,
+};
+use crate::{api, chainloader::*, config, utils, PathExtension};
+
+mod dependencies;
+mod manifest;
+
+/// What discovery found, for the filesystem to be built out of
+pub struct Discovery {
+    pub launchpad: LaunchPad<StandardLoader>,
+    /// Every file of the tree with the root it comes from, from the highest priority mod to the lowest
+    pub files: Vec<(PathBuf, DiscoveredFile)>,
+    /// The files that were collected instead of being put in the tree, as (root, local) pairs
+    pub collected: Vec<(PathBuf, PathBuf)>,
+}
 
-pub fn perform_discovery() -> LaunchPad<StandardLoader> {
+pub fn perform_discovery() -> Discovery {
     let is_emulator = utils::env::is_emulator();
 
     if is_emulator {
         info!("Emulator usage detected in perform_discovery, reverting to old behavior.");
     }
 
-    let mods_path = utils::paths::mods();
-
     let legacy_discovery = config::legacy_discovery();
 
     let mut presets = crate::config::presets::get_active_preset().unwrap();
@@ -33,17 +49,9 @@ pub fn perform_discovery() -> LaunchPad<StandardLoader> {
         let mod_cache: HashSet<Hash40> = config::get_mod_cache().unwrap_or_default();
 
         // Inspect the list of mods to see if some are new ones
-        let new_cache: HashSet<Hash40> = std::fs::read_dir(&mods_path)
-            .unwrap()
-            .filter_map(|path| {
-                let path = PathBuf::from(&mods_path).join(path.unwrap().path());
-
-                if path.is_file() {
-                    None
-                } else {
-                    Some(Hash40::from(path.to_str().unwrap()))
-                }
-            })
+        let new_cache: HashSet<Hash40> = utils::paths::mod_roots()
+            .iter()
+            .map(|path| Hash40::from(path.to_str().unwrap()))
             .collect();
 
         let new_mods: HashSet<&Hash40> = new_cache
@@ -66,6 +74,17 @@ pub fn perform_discovery() -> LaunchPad<StandardLoader> {
     #[cfg(feature = "online")]
     crate::check_input_on_boot();
 
+    let relations = dependencies::read_relations();
+
+    // Dependencies are enabled and incompatibilities disabled through the preset, so this is only possible when presets are used
+    if !is_emulator && !legacy_discovery {
+        let mut preset = config::presets::get_active_preset().unwrap();
+
+        if dependencies::resolve_preset(&relations, &mut preset) {
+            config::presets::replace_active_preset(&preset).unwrap();
+        }
+    }
+
     // If the user edited their mods again, we'll have to reload them here. This is obviously bad and inefficient but it wouldn't be ARCropolis if it wasn't.
     // Consider loading the active presets in a static RwLock so everything can manipulate them without reloading
     let presets = crate::config::presets::get_active_preset().unwrap();
@@ -111,22 +130,6 @@ pub fn perform_discovery() -> LaunchPad<StandardLoader> {
                     "plugin.nro",
                     "bgm_property.bin"
                 ];
-                static PATCH_EXTENSIONS: &[&str] = &[
-                    "prcx",
-                    "prcxml",
-                    "stdatx",
-                    "stdatxml",
-                    "stprmx",
-                    "stprmxml",
-
-                    "xmsbt",
-
-                    "patch3audio",
-
-                    "motdiff",
-
-                    "yml"
-                ];
                 RESERVED_NAMES.contains(&name) || {
                     let is_out_of_region = if let Some(index) = name.find('+') {
                         let (_, end) = name.split_at(index + 1);
@@ -134,102 +137,86 @@ pub fn perform_discovery() -> LaunchPad<StandardLoader> {
                     } else {
                         false
                     };
-                    PATCH_EXTENSIONS.iter().any(|x| name.ends_with(x)) && !is_out_of_region
+                    patches::has_patch_extension(name) && !is_out_of_region
                 }
             },
             _ => false
         }
     };
 
-    let mut launchpad = LaunchPad::new(StandardLoader, ConflictHandler::NoRoot);
+    // Every enabled mod root, from the highest priority to the lowest
+    let mut roots: Vec<PathBuf> = utils::paths::mod_roots().into_iter().filter(|path| filter(path)).collect();
 
-    launchpad.collecting(collect);
-    launchpad.ignoring(ignore);
+    let priority = config::priorities::get_active_priority().unwrap_or_default();
+    config::priorities::sort_by_priority(&mut roots, &priority, PathBuf::clone);
+    dependencies::apply_load_after(&mut roots, &relations);
 
-    let conflicts = launchpad.discover_roots(&mods_path, 1, filter);
+    // Patch files are merged in the same order that decides file conflicts
+    patches::set_mod_priorities(&roots);
 
-    let should_prompt = !conflicts.is_empty();
+    // Roots are discovered in a single pass in priority order, so that on a conflict the file of the mod discovered first is kept
+    // while both mods still provide everything else. Roots that did not change since the last boot are not walked again.
+    let mut cache = DiscoveryCache::load(&roots);
+    let mut launchpad = LaunchPad::new(StandardLoader, ConflictHandler::First);
 
-    for conflict in conflicts.into_iter() {
-        match conflict {
-            ConflictKind::StandardConflict {
-                error_root,
-                source_root,
-                local,
-            } => {
-                warn!(
-                    "File '{}' was rejected for file '{}' during discovery.",
-                    error_root.join(&local).display(),
-                    source_root.join(local).display()
-                )
-            },
-            ConflictKind::RootConflict(root_path, kept) => {
-                warn!(
-                    "Mod root '{}' was rejected for a file conflict with '{}' during discovery.",
-                    root_path.display(),
-                    kept.display()
-                )
-            },
-        }
-    }
+    let mut files: Vec<(PathBuf, DiscoveredFile)> = Vec::new();
+    let mut collected: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut owners: HashMap<PathBuf, usize> = HashMap::new();
+    let mut decisions: BTreeMap<PathBuf, PriorityDecision> = BTreeMap::new();
 
-    if should_prompt
-        && skyline_web::Dialog::yes_no(
-            "During file discovery, ARCropolis encountered file conflicts.<br>Do you want to run it again to list all file conflicts?",
-        )
-    {
-        let mut launchpad = LaunchPad::new(StandardLoader, ConflictHandler::First);
-
-        launchpad.collecting(collect);
-        launchpad.ignoring(ignore);
-
-        let conflicts = launchpad.discover_roots(utils::paths::mods(), 1, filter);
-
-        let mut conflict_map: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
-
-        for conflict in conflicts.into_iter() {
-            if let ConflictKind::StandardConflict {
-                error_root,
-                local,
-                source_root,
-            } = conflict
-            {
-                if let Some(conflicting_mods) = conflict_map.get_mut(&local) {
-                    conflicting_mods.push(error_root);
-                } else {
-                    conflict_map.insert(local, vec![source_root, error_root]);
-                }
+    for (index, root) in roots.iter().enumerate() {
+        // Each root is discovered on its own rather than by listing its mod folder, which also covers the mods inside of collections
+        let discovered = match cache.take(root) {
+            Some(discovered) => discovered,
+            None => walk_root(root, &ignore, &collect),
+        };
+
+        for file in discovered.files.iter() {
+            if let Some(&owner) = owners.get(&file.local) {
+                let source_root = &roots[owner];
+
+                info!(
+                    "File '{}' is provided by '{}', which has priority over '{}'.",
+                    file.local.display(),
+                    source_root.display(),
+                    root.display()
+                );
+
+                let decision = decisions.entry(file.local.clone()).or_insert_with(|| PriorityDecision {
+                    winner: source_root.clone(),
+                    overridden: Vec::new(),
+                });
+
+                decision.overridden.push(root.clone());
+                continue;
             }
+
+            owners.insert(file.local.clone(), index);
+            launchpad.tree_mut().insert_file(root, &file.local);
+            files.push((root.clone(), file.clone()));
         }
 
-        let should_log = match serde_json::to_string_pretty(&conflict_map) {
-            Ok(json) => match std::fs::write("sd:/ultimate/arcropolis/conflicts.json", json.as_bytes()) {
-                Ok(_) => {
-                    crate::dialog_error("Conflict file created at sd:/ultimate/arcropolis/conflicts.json. Please open this file in a text editor to preview what mods are conflicting with one another and take the necessary changes to resolve them by either reslotting or removing these mods.");
-                    false
-                },
-                Err(e) => {
-                    crate::dialog_error(format!(
-                        "Failed to write conflict map to sd:/ultimate/arcropolis/conflicts.json<br>{:?}",
-                        e
-                    ));
-                    true
-                },
-            },
-            Err(e) => {
-                crate::dialog_error(format!("Failed to serialize conflict map to JSON. {:?}", e));
-                true
-            },
-        };
+        collected.extend(discovered.collected.iter().map(|local| (root.clone(), local.clone())));
 
-        if should_log {
-            for (local, roots) in conflict_map {
-                error!("The file {} is used by the following roots:", local.display());
-                for root in roots {
-                    error!("{}", root.display());
-                }
-            }
+        cache.insert(root, discovered);
+    }
+
+    cache.save();
+
+    // Conflicts are only reported while patching files, so whatever is in there is left over from the previous run
+    let _ = std::fs::remove_file(conflicts::CONFLICTS_PATH);
+
+    if !decisions.is_empty() {
+        match conflicts::write_priority_decisions(&decisions) {
+            Ok(_) => info!(
+                "{} file conflicts were resolved by mod priority. See {} for the details.",
+                decisions.len(),
+                conflicts::PRIORITY_DECISIONS_PATH
+            ),
+            Err(e) => error!("{}", e),
         }
+    } else {
+        let _ = std::fs::remove_file(conflicts::PRIORITY_DECISIONS_PATH);
     }
 
     match mount_prebuilt_nrr(launchpad.tree()) {
@@ -243,9 +230,69 @@ pub fn perform_discovery() -> LaunchPad<StandardLoader> {
         },
     }
 
-    load_and_run_plugins(launchpad.collected_paths());
+    load_and_run_plugins(&collected);
+
+    // Sent after the plugins are chainloaded, so that they get to hear about it too
+    let enabled_mods: Vec<&Path> = roots.iter().map(|path| path.as_path()).collect();
+    api::mods::set_enabled_mods(&enabled_mods);
+    api::event::send_event_v2(api::event::QueuedEvent::discovery_finished(&enabled_mods));
 
-    launchpad
+    Discovery { launchpad, files, collected }
+}
+
+/// Walks a mod root, putting aside the files that are collected and leaving out the ignored ones
+fn walk_root(root: &Path, ignore: &dyn Fn(&Path) -> bool, collect: &dyn Fn(&Path) -> bool) -> DiscoveredRoot {
+    fn visit(root: &Path, local: &Path, discovered: &mut DiscoveredRoot, ignore: &dyn Fn(&Path) -> bool, collect: &dyn Fn(&Path) -> bool) {
+        discovered.add_directory(root, local);
+
+        let entries = match std::fs::read_dir(root.join(local)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Failed to read directory '{}'. Reason: {:?}", root.join(local).display(), e);
+                return;
+            },
+        };
+
+        for entry in entries.flatten() {
+            let local = local.join(entry.file_name());
+
+            let is_dir = match entry.file_type() {
+                Ok(file_type) => file_type.is_dir(),
+                Err(_) => continue,
+            };
+
+            if is_dir {
+                let is_dot = local
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| name.starts_with('.'))
+                    .unwrap_or(false);
+
+                if !is_dot {
+                    visit(root, &local, discovered, ignore, collect);
+                }
+            } else if collect(&local) {
+                discovered.collected.push(local);
+            } else if !ignore(&local) {
+                let size = match entry.metadata() {
+                    Ok(metadata) => metadata.len() as usize,
+                    Err(_) => {
+                        error!("Failed to stat file {}. This file may have issues.", root.join(&local).display());
+                        continue;
+                    },
+                };
+
+                match local.smash_hash() {
+                    Ok(hash) => discovered.files.push(DiscoveredFile::new(local, size, hash)),
+                    Err(e) => error!("Failed to get hash for {}. Reason: {:?}", local.display(), e),
+                }
+            }
+        }
+    }
+
+    let mut discovered = DiscoveredRoot::default();
+    visit(root, Path::new(""), &mut discovered, ignore, collect);
+    discovered
 }
 
 fn mount_prebuilt_nrr<A: FileLoader>(tree: &Tree<A>) -> Result<Option<RegistrationInfo>, NrrRegistrationFailedError>
@@ -268,26 +315,28 @@ where
     fighter_nro_nrr.register()
 }
 
-pub fn load_and_run_plugins(plugins: &[(PathBuf, PathBuf)]) {
-    let mut plugin_nrr = NrrBuilder::new();
+fn report_rejected_plugins(rejected: &[(Plugin, RejectReason)]) {
+    if rejected.is_empty() {
+        return;
+    }
+
+    for (plugin, reason) in rejected.iter() {
+        error!("Plugin {} will not be loaded because {}.", plugin, reason);
+    }
+
+    let list: Vec<String> = rejected.iter().map(|(plugin, reason)| format!("{}: {}", plugin.name(), reason)).collect();
+
+    crate::dialog_error(format!("ARCropolis refused to load the following plugins:<br>{}", list.join("<br>")));
+}
 
-    let modules: Vec<NroBuilder> = plugins
+pub fn load_and_run_plugins(plugins: &[(PathBuf, PathBuf)]) {
+    let paths: Vec<PathBuf> = plugins
         .iter()
         .filter_map(|(root, local)| {
             let full_path = root.join(local);
 
             if full_path.exists() && full_path.ends_with("plugin.nro") {
-                match NroBuilder::open(&full_path) {
-                    Ok(builder) => {
-                        info!("Loaded plugin at '{}' for chainloading.", full_path.display());
-                        plugin_nrr.add_module(&builder);
-                        Some(builder)
-                    },
-                    Err(e) => {
-                        error!("Failed to load plugin at '{}'. {:?}", full_path.display(), e);
-                        None
-                    },
-                }
+                Some(full_path)
             } else {
                 error!(
                     "File discovery collected path '{}' but it does not exist and/or is invalid!",
@@ -298,15 +347,44 @@ pub fn load_and_run_plugins(plugins: &[(PathBuf, PathBuf)]) {
         })
         .collect();
 
+    let (plugins, mut rejected) = manifest::order_plugins(paths);
+
+    let mut plugin_nrr = NrrBuilder::new();
+
+    let modules: Vec<(Plugin, NroBuilder)> = plugins
+        .into_iter()
+        .filter_map(|plugin| match NroBuilder::open(&plugin.path) {
+            Ok(builder) => Some((plugin, builder)),
+            Err(e) => {
+                error!("Failed to load plugin at '{}'. {:?}", plugin.path.display(), e);
+                None
+            },
+        })
+        .collect();
+
+    // A plugin that failed to open takes the plugins depending on it down with it
+    let (modules, orphans) = manifest::drop_orphaned_plugins(modules);
+    rejected.extend(orphans);
+
+    for (plugin, builder) in modules.iter() {
+        info!("Loaded plugin {} for chainloading.", plugin);
+        plugin_nrr.add_module(builder);
+    }
+
     if modules.is_empty() {
+        report_rejected_plugins(&rejected);
         info!("No plugins found for chainloading.");
         return;
     }
 
     let mut registration_info = match plugin_nrr.register() {
         Ok(Some(info)) => info,
-        Ok(_) => return,
+        Ok(_) => {
+            report_rejected_plugins(&rejected);
+            return;
+        },
         Err(e) => {
+            report_rejected_plugins(&rejected);
             error!("{:?}", e);
             crate::dialog_error("ARCropolis failed to register plugin module info.");
             return;
@@ -317,23 +395,29 @@ pub fn load_and_run_plugins(plugins: &[(PathBuf, PathBuf)]) {
     // i'm sorry ray, but it literally does not work without collecting here
     // i don't know
     // i didn't write hos
-    let modules: Vec<Module> = modules
+    let modules: Vec<(Plugin, Module)> = modules
         .into_iter()
-        .filter_map(|x| match x.mount() {
-            Ok(module) => Some(module),
+        .filter_map(|(plugin, x)| match x.mount() {
+            Ok(module) => Some((plugin, module)),
             Err(e) => {
-                error!("Failed to mount chainloaded plugin. {:?}", e);
+                error!("Failed to mount chainloaded plugin {}. {:?}", plugin, e);
                 None
             },
         })
         .collect();
 
+    // Same thing for the plugins that failed to mount. Their dependents stay mounted, but their 'main' is never called.
+    let (modules, orphans) = manifest::drop_orphaned_plugins(modules);
+    rejected.extend(orphans);
+
     unsafe {
         // Unfortunately, without unregistering this it will cause the game to crash, cause is unknown, but likely due to page alignment I'd guess
         // It does not matter if we use only one NRR for both the prebuilt modules and the plugins, it will still cause a crash
         nn::ro::UnregisterModuleInfo(&mut registration_info);
     }
 
+    report_rejected_plugins(&rejected);
+
     // 3.0.0: The plugins are apparently loaded despite the mismatch in module vs plugin count, leaving it here for now
     // if modules.len() < plugins.len() {
     //     crate::dialog_error("ARCropolis failed to load/mount some plugins.");
@@ -341,12 +425,13 @@ pub fn load_and_run_plugins(plugins: &[(PathBuf, PathBuf)]) {
     info!("Successfully chainloaded all collected plugins.");
     // }
 
-    for module in modules {
+    // The plugins are already sorted so that dependencies run their 'main' first
+    for (plugin, module) in modules {
         let callable = unsafe {
             let mut sym_loc = 0usize;
             let rc = nn::ro::LookupModuleSymbol(&mut sym_loc, &module, "main\0".as_ptr() as _);
             if rc != 0 {
-                warn!("Failed to find symbol 'main' in chainloaded plugin.");
+                warn!("Failed to find symbol 'main' in chainloaded plugin {}.", plugin);
                 None
             } else {
                 Some(std::mem::transmute::<usize, extern "C" fn()>(sym_loc))
@@ -354,9 +439,9 @@ pub fn load_and_run_plugins(plugins: &[(PathBuf, PathBuf)]) {
         };
 
         if let Some(entrypoint) = callable {
-            info!("Calling 'main' in chainloaded plugin");
+            info!("Calling 'main' in chainloaded plugin {}", plugin);
             entrypoint();
-            info!("Finished calling 'main' in chainloaded plugin");
+            info!("Finished calling 'main' in chainloaded plugin {}", plugin);
         }
     }
 }
@@ -0,0 +1,318 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use smash_arc::Hash40;
+
+use crate::{config, menus::arcadia, utils};
+
+/// The relations a mod declares with other mods in its info.toml
+#[derive(Debug)]
+pub struct ModRelations {
+    pub path: PathBuf,
+    pub folder_name: String,
+    pub mod_id: Option<String>,
+    pub requires: Vec<String>,
+    pub conflicts_with: Vec<String>,
+    pub load_after: Vec<String>,
+}
+
+impl ModRelations {
+    fn hash(&self) -> Hash40 {
+        Hash40::from(self.path.to_str().unwrap_or_default())
+    }
+
+    /// Other mods refer to this one by its folder name or by its mod_id
+    fn is_referred_by(&self, reference: &str) -> bool {
+        self.folder_name == reference || self.mod_id.as_deref() == Some(reference)
+    }
+}
+
+/// Reads the relations of every mod folder, whether it is enabled or not
+pub fn read_relations() -> Vec<ModRelations> {
+    let folders = utils::paths::mod_folders();
+
+    utils::paths::mod_roots()
+        .into_iter()
+        .map(|path| {
+            let entry = match arcadia::read_entry(0, &path, false, &folders) {
+                Ok(entry) => entry,
+                Err((entry, e)) => {
+                    warn!("The info.toml of '{}' is not valid. Reason: {}", path.display(), e);
+                    entry
+                },
+            };
+
+            ModRelations {
+                folder_name: entry.folder_name.unwrap_or_default(),
+                mod_id: entry.mod_id,
+                requires: entry.requires,
+                conflicts_with: entry.conflicts_with,
+                load_after: entry.load_after,
+                path,
+            }
+        })
+        .collect()
+}
+
+fn find<'a>(mods: &'a [ModRelations], reference: &str) -> Option<&'a ModRelations> {
+    mods.iter().find(|other| other.is_referred_by(reference))
+}
+
+/// Checks the relations of the mods enabled in the active preset, and offers to fix the preset when they are not met.
+/// Returns true if the preset was changed.
+pub fn resolve_preset(mods: &[ModRelations], preset: &mut HashSet<Hash40>) -> bool {
+    let mut changed = false;
+
+    // Dependencies can have dependencies of their own, so keep going until everything required is known
+    let mut missing: Vec<(&ModRelations, &ModRelations)> = Vec::new();
+    let mut to_enable: HashSet<Hash40> = HashSet::new();
+
+    loop {
+        let count = to_enable.len();
+
+        for item in mods
+            .iter()
+            .filter(|item| preset.contains(&item.hash()) || to_enable.contains(&item.hash()))
+        {
+            for reference in item.requires.iter() {
+                match find(mods, reference) {
+                    Some(dependency) if !preset.contains(&dependency.hash()) => {
+                        if to_enable.insert(dependency.hash()) {
+                            missing.push((item, dependency));
+                        }
+                    },
+                    Some(_) => {},
+                    None => warn!("Mod '{}' requires '{}', which could not be found.", item.path.display(), reference),
+                }
+            }
+        }
+
+        if to_enable.len() == count {
+            break;
+        }
+    }
+
+    if !missing.is_empty() {
+        let list: Vec<String> = missing
+            .iter()
+            .map(|(item, dependency)| format!("'{}' requires '{}'", item.folder_name, dependency.folder_name))
+            .collect();
+
+        if skyline_web::Dialog::yes_no(format!(
+            "Some enabled mods require mods that are disabled:<br>{}<br><br>Would you like to enable them?",
+            list.join("<br>")
+        )) {
+            preset.extend(to_enable);
+            changed = true;
+        } else {
+            for line in list {
+                warn!("{}, which is disabled.", line);
+            }
+        }
+    }
+
+    // The lower priority mod of each incompatible pair is the one that gets disabled
+    let priority = config::priorities::get_active_priority().unwrap_or_default();
+    let mut ranked: Vec<&ModRelations> = mods.iter().collect();
+    config::priorities::sort_by_priority(&mut ranked, &priority, |item| item.path.clone());
+
+    let rank = |item: &ModRelations| ranked.iter().position(|other| other.path == item.path).unwrap_or(usize::MAX);
+
+    let mut incompatible: Vec<(&ModRelations, &ModRelations)> = Vec::new();
+
+    for item in mods.iter().filter(|item| preset.contains(&item.hash())) {
+        for reference in item.conflicts_with.iter() {
+            if let Some(other) = find(mods, reference).filter(|other| preset.contains(&other.hash()) && other.path != item.path) {
+                let already_listed = incompatible.iter().any(|(a, b)| a.path == other.path && b.path == item.path);
+
+                if !already_listed {
+                    incompatible.push((item, other));
+                }
+            }
+        }
+    }
+
+    if !incompatible.is_empty() {
+        let list: Vec<String> = incompatible
+            .iter()
+            .map(|(item, other)| format!("'{}' is incompatible with '{}'", item.folder_name, other.folder_name))
+            .collect();
+
+        let mut to_disable: HashSet<Hash40> = incompatible
+            .iter()
+            .map(|(item, other)| if rank(item) > rank(other) { item.hash() } else { other.hash() })
+            .collect();
+
+        // Disabling a mod also disables the enabled mods that require it, including the dependencies enabled above
+        let mut dependents: Vec<(&ModRelations, &ModRelations)> = Vec::new();
+
+        loop {
+            let count = to_disable.len();
+
+            for item in mods
+                .iter()
+                .filter(|item| preset.contains(&item.hash()) && !to_disable.contains(&item.hash()))
+            {
+                let disabled_dependency = item
+                    .requires
+                    .iter()
+                    .filter_map(|reference| find(mods, reference))
+                    .find(|dependency| to_disable.contains(&dependency.hash()));
+
+                if let Some(dependency) = disabled_dependency {
+                    to_disable.insert(item.hash());
+                    dependents.push((item, dependency));
+                }
+            }
+
+            if to_disable.len() == count {
+                break;
+            }
+        }
+
+        let dependent_list: Vec<String> = dependents
+            .iter()
+            .map(|(item, dependency)| format!("'{}', which requires '{}'", item.folder_name, dependency.folder_name))
+            .collect();
+
+        let message = if dependent_list.is_empty() {
+            format!(
+                "Some enabled mods are incompatible with each other:<br>{}<br><br>Would you like to disable the lower priority mod of each pair?",
+                list.join("<br>")
+            )
+        } else {
+            format!(
+                "Some enabled mods are incompatible with each other:<br>{}<br><br>Would you like to disable the lower priority mod of each pair? \
+                 This also disables:<br>{}",
+                list.join("<br>"),
+                dependent_list.join("<br>")
+            )
+        };
+
+        if skyline_web::Dialog::yes_no(message) {
+            for item in mods.iter().filter(|item| to_disable.contains(&item.hash())) {
+                info!("Disabling mod '{}' because of an incompatibility.", item.path.display());
+                preset.remove(&item.hash());
+            }
+
+            changed = true;
+        } else {
+            for line in list {
+                warn!("{}, but both are enabled.", line);
+            }
+        }
+    }
+
+    changed
+}
+
+/// Moves every mod in front of the mods it declares to load after, so that it wins their file conflicts.
+/// Mods otherwise keep their order, and mods caught in a cycle keep their order as well.
+pub fn apply_load_after(roots: &mut Vec<PathBuf>, mods: &[ModRelations]) {
+    let relations: Vec<Option<&ModRelations>> = roots.iter().map(|root| mods.iter().find(|item| item.path == *root)).collect();
+
+    // For every root, the roots that have to be discovered before it
+    let before: Vec<Vec<usize>> = (0..roots.len())
+        .map(|index| {
+            relations
+                .iter()
+                .enumerate()
+                .filter(|(other, relation)| {
+                    *other != index
+                        && relation.map_or(false, |relation| {
+                            relation
+                                .load_after
+                                .iter()
+                                .any(|reference| relations[index].map_or(false, |target| target.is_referred_by(reference)))
+                        })
+                })
+                .map(|(other, _)| other)
+                .collect()
+        })
+        .collect();
+
+    let mut placed = vec![false; roots.len()];
+    let mut order = Vec::with_capacity(roots.len());
+
+    while order.len() < roots.len() {
+        let next = (0..roots.len()).find(|&index| !placed[index] && before[index].iter().all(|&other| placed[other]));
+
+        let index = match next {
+            Some(index) => index,
+            None => {
+                let index = (0..roots.len()).find(|&index| !placed[index]).unwrap();
+                warn!("The load_after of mod '{}' is part of a cycle, ignoring it.", roots[index].display());
+                index
+            },
+        };
+
+        placed[index] = true;
+        order.push(index);
+    }
+
+    *roots = order.into_iter().map(|index| roots[index].clone()).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relations(folder_name: &str, mod_id: Option<&str>, load_after: &[&str]) -> ModRelations {
+        ModRelations {
+            path: PathBuf::from(format!("sd:/ultimate/mods/{}", folder_name)),
+            folder_name: folder_name.to_string(),
+            mod_id: mod_id.map(str::to_string),
+            requires: Vec::new(),
+            conflicts_with: Vec::new(),
+            load_after: load_after.iter().map(|reference| reference.to_string()).collect(),
+        }
+    }
+
+    fn order(names: &[&str], mods: &[ModRelations]) -> Vec<String> {
+        let mut roots: Vec<PathBuf> = names.iter().map(|name| PathBuf::from(format!("sd:/ultimate/mods/{}", name))).collect();
+        apply_load_after(&mut roots, mods);
+        roots
+            .iter()
+            .map(|root| root.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_order_without_load_after() {
+        let mods = [relations("a", None, &[]), relations("b", None, &[]), relations("c", None, &[])];
+
+        assert_eq!(order(&["c", "a", "b"], &mods), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn moves_mods_in_front_of_what_they_load_after() {
+        let mods = [relations("a", None, &[]), relations("b", None, &[]), relations("c", None, &["a"])];
+
+        assert_eq!(order(&["a", "b", "c"], &mods), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn refers_to_mods_by_folder_name_or_mod_id() {
+        let mods = [
+            relations("a", Some("base_pack"), &[]),
+            relations("b", None, &["base_pack"]),
+            relations("c", None, &["b"]),
+        ];
+
+        assert_eq!(order(&["a", "b", "c"], &mods), ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn ignores_unknown_references_and_roots_without_relations() {
+        let mods = [relations("a", None, &["missing"]), relations("b", None, &[])];
+
+        assert_eq!(order(&["b", "unlisted", "a"], &mods), ["b", "unlisted", "a"]);
+    }
+
+    #[test]
+    fn keeps_the_order_of_mods_in_a_cycle() {
+        let mods = [relations("a", None, &["b"]), relations("b", None, &["a"]), relations("c", None, &[])];
+
+        // The cycle is only broken once nothing else can be placed
+        assert_eq!(order(&["a", "b", "c"], &mods), ["c", "a", "b"]);
+    }
+}
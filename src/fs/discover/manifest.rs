@@ -0,0 +1,200 @@
+use std::{collections::HashSet, fmt, path::PathBuf};
+
+use serde::Deserialize;
+
+/// The optional plugin.toml placed next to a plugin.nro
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: Option<String>,
+    /// The minimum ARCropolis API version required by the plugin, as `major.minor`
+    pub api_version: Option<String>,
+    /// The names of the plugins that have to be loaded before this one
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+}
+
+/// A plugin.nro found during discovery, along with its manifest if it has one
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    pub path: PathBuf,
+    pub manifest: Option<PluginManifest>,
+}
+
+impl Plugin {
+    /// Reads the manifest next to a plugin.nro, if there is one
+    pub fn read(path: PathBuf) -> Result<Self, (Self, RejectReason)> {
+        let manifest_path = path.with_file_name("plugin.toml");
+
+        if !manifest_path.exists() {
+            return Ok(Self { path, manifest: None });
+        }
+
+        let manifest = std::fs::read_to_string(&manifest_path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str::<PluginManifest>(&text).map_err(|e| e.to_string()));
+
+        match manifest {
+            Ok(manifest) => Ok(Self {
+                path,
+                manifest: Some(manifest),
+            }),
+            Err(e) => Err((Self { path, manifest: None }, RejectReason::InvalidManifest(e))),
+        }
+    }
+
+    /// The name other plugins refer to this one by. Plugins without a manifest are named after their mod folder.
+    pub fn name(&self) -> String {
+        match &self.manifest {
+            Some(manifest) => manifest.name.clone(),
+            None => self
+                .path
+                .parent()
+                .and_then(|parent| parent.file_name())
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| self.path.display().to_string()),
+        }
+    }
+
+    fn dependencies(&self) -> &[String] {
+        self.manifest.as_ref().map_or(&[], |manifest| manifest.dependencies.as_slice())
+    }
+}
+
+impl fmt::Display for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.manifest.as_ref().and_then(|manifest| manifest.version.as_ref()) {
+            Some(version) => write!(f, "{} {} ({})", self.name(), version, self.path.display()),
+            None => write!(f, "{} ({})", self.name(), self.path.display()),
+        }
+    }
+}
+
+/// Why a plugin is not going to be chainloaded
+#[derive(Debug, Clone)]
+pub enum RejectReason {
+    InvalidManifest(String),
+    DuplicateName,
+    UnsupportedApiVersion(String),
+    MissingDependency(String),
+    DependencyCycle,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidManifest(e) => write!(f, "its plugin.toml is invalid: {}", e),
+            Self::DuplicateName => write!(f, "another plugin has the same name"),
+            Self::UnsupportedApiVersion(version) => write!(f, "it requires ARCropolis API {}", version),
+            Self::MissingDependency(name) => write!(f, "it depends on '{}', which is not loaded", name),
+            Self::DependencyCycle => write!(f, "its dependencies form a cycle"),
+        }
+    }
+}
+
+fn is_api_version_supported(version: &str) -> bool {
+    let mut parts = version.trim().splitn(2, '.');
+
+    let major = parts.next().and_then(|major| major.parse::<u32>().ok());
+    let minor = parts.next().map_or(Some(0), |minor| minor.parse::<u32>().ok());
+
+    match (major, minor) {
+        (Some(major), Some(minor)) => crate::api::is_api_version_supported(major, minor),
+        _ => false,
+    }
+}
+
+/// Drops the plugins whose dependencies did not make it this far, like the ones that failed to open or mount.
+/// The plugins have to be ordered by `order_plugins` already, so every dependency comes before its dependents.
+pub fn drop_orphaned_plugins<T>(plugins: Vec<(Plugin, T)>) -> (Vec<(Plugin, T)>, Vec<(Plugin, RejectReason)>) {
+    let mut names = HashSet::new();
+    let mut kept = Vec::with_capacity(plugins.len());
+    let mut rejected = Vec::new();
+
+    for (plugin, data) in plugins {
+        match plugin.dependencies().iter().find(|dependency| !names.contains(*dependency)).cloned() {
+            Some(dependency) => rejected.push((plugin, RejectReason::MissingDependency(dependency))),
+            None => {
+                names.insert(plugin.name());
+                kept.push((plugin, data));
+            },
+        }
+    }
+
+    (kept, rejected)
+}
+
+/// Reads the manifests of the plugins and orders them so that each one comes after its dependencies, keeping the discovery order otherwise.
+/// Returns the plugins to load, and the ones that cannot be loaded along with the reason.
+pub fn order_plugins(paths: Vec<PathBuf>) -> (Vec<Plugin>, Vec<(Plugin, RejectReason)>) {
+    let mut rejected = Vec::new();
+    let mut candidates: Vec<Plugin> = Vec::new();
+
+    for path in paths {
+        let plugin = match Plugin::read(path) {
+            Ok(plugin) => plugin,
+            Err(rejection) => {
+                rejected.push(rejection);
+                continue;
+            },
+        };
+
+        let required = plugin.manifest.as_ref().and_then(|manifest| manifest.api_version.clone());
+
+        if let Some(version) = required.filter(|version| !is_api_version_supported(version)) {
+            rejected.push((plugin, RejectReason::UnsupportedApiVersion(version)));
+        } else if candidates.iter().any(|candidate| candidate.name() == plugin.name()) {
+            rejected.push((plugin, RejectReason::DuplicateName));
+        } else {
+            candidates.push(plugin);
+        }
+    }
+
+    // Rejecting a plugin can leave others with a missing dependency, so repeat until nothing changes
+    loop {
+        let names: HashSet<String> = candidates.iter().map(Plugin::name).collect();
+
+        let missing = candidates
+            .iter()
+            .position(|plugin| plugin.dependencies().iter().any(|dependency| !names.contains(dependency)));
+
+        match missing {
+            Some(index) => {
+                let plugin = candidates.remove(index);
+                let dependency = plugin
+                    .dependencies()
+                    .iter()
+                    .find(|dependency| !names.contains(*dependency))
+                    .unwrap()
+                    .clone();
+                rejected.push((plugin, RejectReason::MissingDependency(dependency)));
+            },
+            None => break,
+        }
+    }
+
+    let mut remaining: Vec<usize> = candidates.iter().map(|plugin| plugin.dependencies().len()).collect();
+    let mut loaded = vec![false; candidates.len()];
+    let mut order = Vec::with_capacity(candidates.len());
+
+    // Always pick the first plugin in discovery order whose dependencies are all loaded
+    while let Some(index) = (0..candidates.len()).find(|&index| !loaded[index] && remaining[index] == 0) {
+        loaded[index] = true;
+        order.push(index);
+
+        let name = candidates[index].name();
+
+        for (other, plugin) in candidates.iter().enumerate() {
+            remaining[other] -= plugin.dependencies().iter().filter(|dependency| **dependency == name).count();
+        }
+    }
+
+    let mut candidates: Vec<Option<Plugin>> = candidates.into_iter().map(Some).collect();
+
+    let ordered = order.into_iter().map(|index| candidates[index].take().unwrap()).collect();
+
+    // Whatever is left either is in a cycle or depends on one
+    rejected.extend(candidates.into_iter().flatten().map(|plugin| (plugin, RejectReason::DependencyCycle)));
+
+    (ordered, rejected)
+}
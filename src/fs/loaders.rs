@@ -1,45 +1,9 @@
-use std::{
-    collections::VecDeque,
-    fs::{self, File},
-    io::{Cursor, Read},
-};
-
-use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine};
-use hash40::diff::Diff;
-use msbt::{builder::MsbtBuilder, Msbt};
-use nus3audio::*;
-use serde::*;
-use serde_xml_rs;
-use serde_yaml::from_str;
-use smash_bgm_property::BgmPropertyFile;
-use xml::common::Position;
-
-use super::*;
-
-#[derive(Debug, Deserialize)]
-pub struct Xmsbt {
-    #[serde(rename = "entry")]
-    entries: Vec<Entry>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct Entry {
-    label: String,
-    base64: Option<bool>,
-    #[serde(rename = "text")]
-    text: Text,
-}
+use std::{collections::VecDeque, sync::Arc};
 
-#[derive(Debug, Deserialize)]
-pub struct Text {
-    #[serde(rename = "$value")]
-    value: String,
-}
-
-pub enum TextType {
-    Text(String),
-    Data(Vec<u8>),
-}
+use super::{
+    patches::{self, PatchFile, PatchHandler},
+    *,
+};
 
 #[derive(Debug, Error)]
 pub enum ApiLoaderError {
@@ -65,51 +29,51 @@ pub enum ApiLoaderError {
     Other(String),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Clone)]
 enum ApiLoadType {
-    Nus3bankPatch,
-    PrcPatch,
-    MsbtPatch,
-    Nus3audioPatch,
-    MotionlistPatch,
-    BgmPropertyPatch,
+    Patch(Arc<dyn PatchHandler>),
     Generic,
     Stream,
-    Extension,
+    StreamData,
+    Virtual,
 }
 
 impl ApiLoadType {
     pub fn from_root(root: &Path) -> Result<Self, ApiLoaderError> {
-        if root.ends_with("patch-nus3bank") {
-            Ok(ApiLoadType::Nus3bankPatch)
-        } else if root.ends_with("patch-prc") {
-            Ok(ApiLoadType::PrcPatch)
-        } else if root.ends_with("patch-msbt") {
-            Ok(ApiLoadType::MsbtPatch)
-        } else if root.ends_with("patch-nus3audio") {
-            Ok(ApiLoadType::Nus3audioPatch)
-        } else if root.ends_with("patch-motionlist") {
-            Ok(ApiLoadType::MotionlistPatch)
-        } else if root.ends_with("patch-bgm_property") {
-            Ok(ApiLoadType::BgmPropertyPatch)
+        let patch_handler = root
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("patch-"))
+            .and_then(patches::handler_by_name);
+
+        if let Some(handler) = patch_handler {
+            Ok(ApiLoadType::Patch(handler))
         } else if root.ends_with("generic-cb") {
             Ok(ApiLoadType::Generic)
         } else if root.ends_with("stream-cb") {
             Ok(ApiLoadType::Stream)
-        } else if root.ends_with("extension-cb") {
-            Ok(ApiLoadType::Extension)
+        } else if root.ends_with("stream-data-cb") {
+            Ok(ApiLoadType::StreamData)
+        } else if root.ends_with("virtual-file") {
+            Ok(ApiLoadType::Virtual)
         } else {
             Err(ApiLoaderError::Other(format!("Cannot find ApiLoadType for root {}", root.display())))
         }
     }
 
-    pub fn path_exists(self, _local: &Path) -> bool {
-        matches!(self, ApiLoadType::Nus3bankPatch)
+    pub fn path_exists(&self, _local: &Path) -> bool {
+        match self {
+            ApiLoadType::Patch(handler) => handler.serves_vanilla(),
+            ApiLoadType::StreamData | ApiLoadType::Virtual => true,
+            _ => false,
+        }
     }
 
-    pub fn get_file_size(self, local: &Path) -> Option<usize> {
+    pub fn get_file_size(&self, local: &Path, usr_fn: ApiCallback) -> Option<usize> {
         match self {
-            ApiLoadType::Nus3bankPatch => {
+            ApiLoadType::StreamData if let ApiCallback::StreamDataCallback(_, size) = usr_fn => Some(size),
+            ApiLoadType::Virtual if let ApiCallback::VirtualFile(data) = &usr_fn => Some(data.len()),
+            ApiLoadType::Patch(handler) if handler.serves_vanilla() => {
                 let arc = resource::arc();
                 crate::get_smash_hash(local)
                     .ok()
@@ -120,9 +84,10 @@ impl ApiLoadType {
         }
     }
 
-    pub fn get_path_type(self, local: &Path) -> Result<FileEntryType, ApiLoaderError> {
+    pub fn get_path_type(&self, local: &Path) -> Result<FileEntryType, ApiLoaderError> {
         match self {
-            ApiLoadType::Nus3bankPatch => {
+            ApiLoadType::StreamData | ApiLoadType::Virtual => Ok(FileEntryType::File),
+            ApiLoadType::Patch(handler) if handler.serves_vanilla() => {
                 let search = resource::search();
                 let hash = crate::get_smash_hash(local)?;
                 if search.get_path_list_entry_from_hash(hash)?.is_directory() {
@@ -135,289 +100,31 @@ impl ApiLoadType {
         }
     }
 
-    pub fn load_path(self, local: &Path, usr_fn: ApiCallback) -> Result<(usize, Vec<u8>), ApiLoaderError> {
+    pub fn load_path(&self, local: &Path, usr_fn: ApiCallback) -> Result<(usize, Vec<u8>), ApiLoaderError> {
         println!("[ARCropolis::loader] Patching {:#?}", local.as_os_str());
 
         match self {
-            ApiLoadType::Nus3bankPatch => {
-                let data = ApiLoader::handle_load_vanilla_file(local)?;
-                Ok((data.len(), data))
-            },
-            ApiLoadType::PrcPatch => {
-                let patches = if let Some(patches) = ApiLoader::get_prc_patches_for_hash(local.smash_hash()?) {
-                    patches
-                } else {
-                    return Err(ApiLoaderError::Other("[ARCropolis::loader] No patches found for file of type PRC!".to_string()));
-                };
-
-                let data = ApiLoader::handle_load_base_file(local)?;
-                let mut param_data = prcx::read_stream(&mut Cursor::new(data))
-                    .map_err(|_| ApiLoaderError::Other("Unable to parse param data!".to_string()))?;
-
-                for patch_path in patches.iter() {
-                    let patch = if let Ok(patch) = prcx::open(patch_path) {
-                        patch
-                    } else {
-                        let file = File::open(patch_path)?;
-                        let mut reader = std::io::BufReader::new(file);
-
-                        prcx::read_xml(&mut reader).map_err(|_| ApiLoaderError::Other("Unable to parse param patch data!".to_string()))?
-                    };
-
-                    prcx::apply_patch(&patch, &mut param_data).map_err(|_| ApiLoaderError::Other("Unable to patch param data!".to_string()))?;
-                }
-
-                let mut writer = Cursor::new(Vec::new());
-                prcx::write_stream(&mut writer, &param_data)?;
-                let data = writer.into_inner();
-                Ok((data.len(), data))
-            },
-            ApiLoadType::MsbtPatch => {
-                let patches = if let Some(patches) = ApiLoader::get_msbt_patches_for_hash(local.smash_hash()?) {
-                    patches
-                } else {
-                    return Err(ApiLoaderError::Other("No patches found for file of type MSBT!".to_string()));
-                };
-
-                let mut labels: HashMap<String, TextType> = HashMap::new();
-
-                for patch_path in patches.iter() {
-                    let mut reader = Cursor::new(fs::read(patch_path)?);
-                    let xmsbt: Xmsbt = match serde_xml_rs::from_reader(&mut reader) {
-                        Ok(xmsbt) => xmsbt,
-                        Err(err) => {
-                            match err {
-                                serde_xml_rs::Error::Syntax { source }  => {
-                                    let position = source.position();
-                                    warn!("XMSBT file `{}` could not be read due to the following syntax error at line {}, column {}: `{}`, skipping.", patch_path.display(), position.row + 1, position.column, source.msg())
-                                },
-                                _ => warn!("XMSBT file `{}` is malformed, skipping.", patch_path.display()),
-                            }
-
-                            continue;
-                        },
-                    };
-
-                    for entry in &xmsbt.entries {
-                        if entry.base64.unwrap_or(false) {
-                            match BASE64_STANDARD.decode::<String>(entry.text.value.to_owned()) {
-                                Ok(mut decoded) => {
-                                    // Pushing these 0s to ensure that the end of the text is marked clearly
-                                    decoded.push(0);
-                                    decoded.push(0);
-                                    labels.insert(entry.label.to_owned(), TextType::Data(decoded));
-                                },
-                                Err(err) => error!("XMSBT Label {} could not be base64 decoded. Reason: {}", entry.label, err),
-                            }
-                        } else {
-                            labels.insert(entry.label.to_owned(), TextType::Text(entry.text.value.to_owned()));
-                        }
-                    }
-                }
-
-                let data = ApiLoader::handle_load_base_file(local)?;
-                let mut msbt = Msbt::from_reader(Cursor::new(&data)).unwrap();
-
-                for lbl in msbt.lbl1_mut().unwrap().labels_mut() {
-                    let lbl_name = &lbl.name().to_owned();
-
-                    if labels.contains_key(lbl_name) {
-                        let text_data = match &labels[lbl_name] {
-                            TextType::Text(text) => {
-                                let mut str_val: Vec<u16> = text.encode_utf16().collect();
-                                str_val.push(0);
-
-                                let slice_u8: &[u8] = unsafe {
-                                    std::slice::from_raw_parts(
-                                        str_val.as_ptr() as *const u8,
-                                        str_val.len() * std::mem::size_of::<u16>(),
-                                    )
-                                };
-
-                                slice_u8
-                            },
-                            TextType::Data(data) => &data,
-                        };
-
-                        lbl.set_value_raw(text_data).unwrap();
-                        labels.remove(lbl_name);
-                    }
-                }
-
-                let mut builder = MsbtBuilder::from(msbt);
-
-                for lbl in labels {
-                    let text_data = match &lbl.1 {
-                        TextType::Text(text) => {
-                            let mut str_val: Vec<u16> = text
-                                    .encode_utf16()
-                                    .collect();
-
-                            str_val.push(0);
-
-                            let slice_u8: &[u8] = unsafe {
-                                std::slice::from_raw_parts(
-                                    str_val.as_ptr() as *const u8,
-                                    str_val.len() * std::mem::size_of::<u16>(),
-                                )
-                            };
-
-                            slice_u8
-                        },
-                        TextType::Data(data) => &data,
-                    };
-
-                    builder = builder.add_label(lbl.0, text_data);
-                }
-
-                let out_msbt = builder.build();
-                let mut cursor = Cursor::new(Vec::new());
-                out_msbt.write_to(&mut cursor).unwrap();
-                let data = cursor.into_inner();
-                Ok((data.len(), data))
-            },
-            ApiLoadType::Nus3audioPatch => {
-                let patches = if let Some(patches) = ApiLoader::get_nus3audio_patches_for_hash(local.smash_hash()?) {
-                    patches
-                } else {
-                    return Err(ApiLoaderError::Other("No patches found for file of type NUS3AUDIO!".to_string()));
-                };
-
-                // Initialize the `original_file` variable, which parses the pre patch file into the nus3audio type
-                let mut original_file = Nus3audioFile::from_bytes(&ApiLoader::handle_load_base_file(local).unwrap()[..]);
-
-                // This is a little weird imo, but it's the only good solution I could come up with
-                // Basically what it's doing past this point is:
-                //     ~ looping through the original file's audiofiles to get their names and insert the name
-                //     and itself into the HashMap
-                //     ~ looping through the patches, and then applying them to the HashMap
-                //     ~ setting the base file's AudioFile vec to the values of the HashMap
-                let mut known_audiofiles: HashMap<String, AudioFile> = original_file.files.iter().map(|audio_file| (audio_file.name.clone(), audio_file.clone())).collect();
-
-                // Iterate through the patches
-                for patch_path in patches.iter() {
-                    // Reads the patch file data and parses it into the nus3audio type
-                    let patch_data = &std::fs::read(patch_path).unwrap()[..];
-                    let modified_file = Nus3audioFile::from_bytes(patch_data);
-
-                    // Iterate through the AudioFiles of the modified file
-                    for mut audio_file in modified_file.files {
-                        // Check if the known AudioFiles HashMap contains the name of the current AudioFile
-                        if known_audiofiles.contains_key(&audio_file.name) {
-                            // If it does, set the already made AudioFile's data to the modified one/
-                            println!("Found {}! Patching...", &audio_file.name);
-                            known_audiofiles.get_mut(&audio_file.name).unwrap().data = audio_file.data.clone();
-                        }
-                        else {
-                            // If it doesn't, insert it into the known_audiofiles HashMap
-                            println!("Not found {}! Adding...", &audio_file.name);
-                            audio_file.id = (known_audiofiles.len() + 1) as u32;
-                            known_audiofiles.try_insert(audio_file.name.clone(), audio_file.clone()).unwrap();
-                        }
-                    }
-                }
-
-                // Initialize the `new_audio_files` Vec, which takes in the values of the known_audiofiles HashMap
-                let mut new_audio_files: Vec<AudioFile> = known_audiofiles.iter().map(|(_, audio_file)| audio_file.clone()).collect();
-
-                // Sort the new_audio_files vec by ID, because if we don't, the game loads the wrong AudioFiles on request.
-                new_audio_files.sort_by(|a, b| a.id.cmp(&b.id));
-
-                // Set the original file's AudioFile vec to the new_audio_files vec
-                original_file.files = new_audio_files;
-
-                let mut contents: Vec<u8> = Vec::new();
-
-                // Write the contents of the original file to the contents vec
-                original_file.write(&mut contents);
-
-                // Return the length of the contents and the contents
-                Ok((contents.len(), contents))
-
-            },
-            ApiLoadType::MotionlistPatch => {
-                let patches = if let Some(patches) = ApiLoader::get_motionlist_patches_for_hash(local.smash_hash()?) {
-                    patches
-                } else {
-                    return Err(ApiLoaderError::Other("[ARCropolis::loader] No patches found for files motion_list.bin!".to_string()));
-                };
-
-                let mut yml_patches = Vec::new();
-                let mut diff_patches = Vec::new();
-
-                for patch_path in patches.iter() {
-                    if patch_path.has_extension("motdiff") {
-                        diff_patches.push(patch_path.clone());
-                    } else if patch_path.ends_with("motion_list.yml") {
-                        yml_patches.push(patch_path.clone());
-                    } else {
-                        return Err(ApiLoaderError::Other("This isn't a motion list patch file!".to_string()));
-                    }
-                }
-
-                let data = ApiLoader::handle_load_base_file(local)?;
-                let mut reader = Cursor::new(data);
-                let mut motion_list = motion_lib::read_stream(&mut reader)?;
-
-                if !yml_patches.is_empty() {
-                    println!("[ARCropolis::loader] motion_list.yml file(s) found!");
-                    let mut full_patches = 0;
-
-                    for full_patch in yml_patches.iter() {
-                        println!("[ARCropolis::loader] Replacing motion_list.bin with {}.", full_patch.to_str().unwrap());
-                        let mut contents: String = String::default();
-                        File::open(full_patch)?.read_to_string(&mut contents)?;
-                        if let Some(full) = from_str(&contents)? {
-                            motion_list = full;
-                            full_patches += 1;
-                        }
-                    }
-
-                    if full_patches > 1 {
-                        println!("[ARCropolis::loader] Multiple motion_list.yml files found for {}.", local.to_str().unwrap());
-                        println!("                     The last applied .yml file will be used.");
-                    }
-                }
-
-                if !diff_patches.is_empty() {
-                    for patch_path in diff_patches.iter() {
-                        let mut contents: String = String::default();
-                        File::open(patch_path)?.read_to_string(&mut contents)?;
-                        if let Some(diff) = from_str(&contents)? {
-                            motion_list.apply(&diff);
-                        }
-                        else {
-                            return Err(ApiLoaderError::Other("This isn't a motion list patch file!".to_string()));
-                        }
-                    }
-                }
-
-                println!("[ARCropolis::loader] 'motion_list.bin' patching finished!");
-                let mut writer = Cursor::new(Vec::new());
-                motion_lib::write_stream(&mut writer, &motion_list)?;
-                let data = writer.into_inner();
-                Ok((data.len(), data))
-            },
-            ApiLoadType::BgmPropertyPatch => {
-                let patches = if let Some(patches) = ApiLoader::get_bgm_property_patches_for_hash(local.smash_hash()?) {
-                    patches
-                } else {
-                    return Err(ApiLoaderError::Other("[ARCropolis::loader] No patches found for file bgm_property.bin!".to_string()));
+            ApiLoadType::Patch(handler) => {
+                let patches = match ApiLoader::get_patches_for_hash(local.smash_hash()?) {
+                    Some(patches) if !patches.is_empty() => patches,
+                    // Files without patch files are only here to be unshared, so the vanilla data is enough
+                    _ if handler.serves_vanilla() => {
+                        let data = ApiLoader::handle_load_vanilla_file(local)?;
+                        return Ok((data.len(), data));
+                    },
+                    _ => {
+                        return Err(ApiLoaderError::Other(format!(
+                            "[ARCropolis::loader] No patches found for file of type {}!",
+                            handler.name()
+                        )))
+                    },
                 };
 
                 let data = ApiLoader::handle_load_base_file(local)?;
-                let mut reader = Cursor::new(&data[..]);
-                let mut bgm_property = BgmPropertyFile::read(&mut reader).unwrap();
+                let data = handler
+                    .patch(local, data, patches)
+                    .map_err(|e| ApiLoaderError::Other(format!("Unable to patch {} data! {}", handler.name(), e)))?;
 
-                for patch_path in patches.iter() {
-                    let mut patch_file = BgmPropertyFile::from_file(patch_path).unwrap();
-
-                    bgm_property.entries.append(&mut patch_file.entries);
-                }
-
-                let mut writer = Cursor::new(Vec::new());
-                bgm_property.write(&mut writer).unwrap();
-                let data = writer.into_inner();
                 Ok((data.len(), data))
             },
             ApiLoadType::Generic if let ApiCallback::GenericCallback(cb) = usr_fn => {
@@ -459,16 +166,60 @@ impl ApiLoadType {
                 Ok((file_size, vec))
             },
             ApiLoadType::Stream => Err(ApiLoaderError::InvalidCb),
-            _ => Err(ApiLoaderError::Other("Unimplemented ApiLoadType!".to_string()))
+            ApiLoadType::StreamData if let ApiCallback::StreamDataCallback(cb, size) = usr_fn => {
+                let hash = local.smash_hash()?;
+                let mut vec = vec![0u8; size];
+                let mut offset = 0;
+
+                // The callback can provide less than what was asked for, so keep reading until everything is there
+                while offset < size {
+                    let mut read = 0;
+
+                    if !cb(hash.0, vec[offset..].as_mut_ptr(), size - offset, offset, &mut read) || read == 0 {
+                        return Err(ApiLoaderError::Other("Callback did not provide the stream data!".to_string()));
+                    }
+
+                    offset += read.min(size - offset);
+                }
+
+                Ok((size, vec))
+            },
+            ApiLoadType::StreamData => Err(ApiLoaderError::InvalidCb),
+            ApiLoadType::Virtual if let ApiCallback::VirtualFile(data) = &usr_fn => Ok((data.len(), data.to_vec())),
+            ApiLoadType::Virtual => Err(ApiLoaderError::InvalidCb),
         }
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum ApiCallback {
     None,
     GenericCallback(arcropolis_api::CallbackFn),
     StreamCallback(arcropolis_api::StreamCallbackFn),
+    StreamDataCallback(crate::api::StreamDataCallbackFn, usize),
+    VirtualFile(Arc<[u8]>),
+}
+
+impl ApiCallback {
+    /// The address of the plugin function behind this callback, used to find it again when unregistering it
+    pub fn address(&self) -> Option<usize> {
+        match self {
+            ApiCallback::GenericCallback(cb) => Some(*cb as usize),
+            ApiCallback::StreamCallback(cb) => Some(*cb as usize),
+            ApiCallback::StreamDataCallback(cb, _) => Some(*cb as usize),
+            ApiCallback::None | ApiCallback::VirtualFile(_) => None,
+        }
+    }
+
+    /// The kind of plugin callback, as shown in the source of a file
+    pub fn kind(&self) -> Option<&'static str> {
+        match self {
+            ApiCallback::GenericCallback(_) => Some("generic"),
+            ApiCallback::StreamCallback(_) => Some("stream"),
+            ApiCallback::StreamDataCallback(..) => Some("stream data"),
+            ApiCallback::None | ApiCallback::VirtualFile(_) => None,
+        }
+    }
 }
 
 #[repr(transparent)]
@@ -486,11 +237,8 @@ struct ApiFunctionEntry {
 pub struct ApiLoader {
     function_map: HashMap<Hash40, UnsafeCell<ApiFunctionEntry>>,
     stream_size_map: UnsafeCell<HashMap<PathBuf, usize>>,
-    param_patches: HashMap<Hash40, Vec<PathBuf>>,
-    msbt_patches: HashMap<Hash40, Vec<PathBuf>>,
-    nus3audio_patches: HashMap<Hash40, Vec<PathBuf>>,
-    motionlist_patches: HashMap<Hash40, Vec<PathBuf>>,
-    bgm_property_patches: HashMap<Hash40, Vec<PathBuf>>,
+    patch_files: HashMap<Hash40, Vec<PatchFile>>,
+    size_callbacks: HashMap<Hash40, crate::api::SizeCallbackFn>,
 }
 
 unsafe impl Send for ApiLoader {}
@@ -521,7 +269,7 @@ impl ApiLoader {
             unsafe {
                 if let Some((vroot, func)) = (*data).functions.get((*data).function_index) {
                     (*data).function_index += 1;
-                    Some((vroot.as_path(), *func))
+                    Some((vroot.as_path(), func.clone()))
                 } else {
                     None
                 }
@@ -557,79 +305,98 @@ impl ApiLoader {
         }
     }
 
-    pub fn get_prc_patches_for_hash(hash: Hash40) -> Option<&'static Vec<PathBuf>> {
+    pub fn get_patches_for_hash(hash: Hash40) -> Option<&'static Vec<PatchFile>> {
         let filesystem = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
         let cached = filesystem.get();
 
-        cached.virt().loader.param_patches.get(&hash)
+        cached.virt().loader.patch_files.get(&hash)
     }
 
-    pub fn get_msbt_patches_for_hash(hash: Hash40) -> Option<&'static Vec<PathBuf>> {
-        let filesystem = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
-        let cached = filesystem.get();
-
-        cached.virt().loader.msbt_patches.get(&hash)
+    pub fn insert_patch(&mut self, hash: Hash40, root: &Path, path: &Path) {
+        patches::insert_by_priority(self.patch_files.entry(hash).or_default(), PatchFile::new(root, path));
     }
 
-    pub fn get_nus3audio_patches_for_hash(hash: Hash40) -> Option<&'static Vec<PathBuf>> {
-        let filesystem = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
-        let cached = filesystem.get();
+    /// Removes the callback with the given address from the chain of a file, and returns it.
+    /// The file is removed from the API tree lookups once its chain is empty.
+    pub fn remove_callback(&mut self, hash: Hash40, address: usize) -> Option<ApiCallback> {
+        let entry = self.function_map.get_mut(&hash)?.get_mut();
+        let index = entry.functions.iter().position(|(_, callback)| callback.address() == Some(address))?;
+        let (_, callback) = entry.functions.remove(index)?;
 
-        cached.virt().loader.nus3audio_patches.get(&hash)
-    }
+        // The chain is only walked while the filesystem is locked, so there is no index left pointing into it
+        entry.function_index = 0;
 
-    pub fn get_motionlist_patches_for_hash(hash: Hash40) -> Option<&'static Vec<PathBuf>> {
-        let filesystem = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
-        let cached = filesystem.get();
+        if !entry.functions.iter().any(|(_, callback)| matches!(callback, ApiCallback::GenericCallback(_))) {
+            self.size_callbacks.remove(&hash);
+        }
 
-        cached.virt().loader.motionlist_patches.get(&hash)
-    }
+        if entry.functions.is_empty() {
+            self.function_map.remove(&hash);
+        }
 
-    pub fn get_bgm_property_patches_for_hash(hash: Hash40) -> Option<&'static Vec<PathBuf>> {
-        let filesystem = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
-        let cached = filesystem.get();
+        if let ApiCallback::StreamCallback(_) = callback {
+            self.stream_size_map.get_mut().retain(|path, _| path.smash_hash().ok() != Some(hash));
+        }
 
-        cached.virt().loader.bgm_property_patches.get(&hash)
+        Some(callback)
     }
 
-    pub fn insert_prc_patch(&mut self, hash: Hash40, path: &Path) {
-        if let Some(list) = self.param_patches.get_mut(&hash) {
-            list.push(path.to_path_buf())
-        } else {
-            self.param_patches.insert(hash, vec![path.to_path_buf()]);
+    /// Swaps the callback with the given address for a new one, keeping its place in the chain of the file
+    pub fn replace_callback(&mut self, hash: Hash40, address: usize, new: ApiCallback) -> bool {
+        let entry = match self.function_map.get_mut(&hash) {
+            Some(entry) => entry.get_mut(),
+            None => return false,
+        };
+
+        let same_callback =
+            |callback: &ApiCallback| callback.address() == Some(address) && std::mem::discriminant(callback) == std::mem::discriminant(&new);
+
+        match entry.functions.iter_mut().find(|(_, callback)| same_callback(callback)) {
+            Some((_, callback)) => {
+                *callback = new;
+                true
+            },
+            None => false,
         }
     }
 
-    pub fn insert_msbt_patch(&mut self, hash: Hash40, path: &Path) {
-        if let Some(list) = self.msbt_patches.get_mut(&hash) {
-            list.push(path.to_path_buf())
-        } else {
-            self.msbt_patches.insert(hash, vec![path.to_path_buf()]);
+    /// Replaces the data of a virtual file that was already added. Returns false if there is no virtual file for this hash.
+    pub fn replace_virtual_file(&mut self, hash: Hash40, data: &Arc<[u8]>) -> bool {
+        let entry = match self.function_map.get_mut(&hash) {
+            Some(entry) => entry.get_mut(),
+            None => return false,
+        };
+
+        match entry.functions.iter_mut().find(|(_, callback)| matches!(callback, ApiCallback::VirtualFile(_))) {
+            Some((_, callback)) => {
+                *callback = ApiCallback::VirtualFile(data.clone());
+                true
+            },
+            None => false,
         }
     }
 
-    pub fn insert_nus3audio_patch(&mut self, hash: Hash40, path: &Path) {
-        if let Some(list) = self.nus3audio_patches.get_mut(&hash) {
-            list.push(path.to_path_buf())
-        } else {
-            self.nus3audio_patches.insert(hash, vec![path.to_path_buf()]);
-        }
+    /// Sets the callback that provides the exact size of a file right before it is loaded
+    pub fn set_size_callback(&mut self, hash: Hash40, callback: crate::api::SizeCallbackFn) {
+        self.size_callbacks.insert(hash, callback);
     }
 
-    pub fn insert_motionlist_patch(&mut self, hash: Hash40, path: &Path) {
-        if let Some(list) = self.motionlist_patches.get_mut(&hash) {
-            list.push(path.to_path_buf())
-        } else {
-            self.motionlist_patches.insert(hash, vec![path.to_path_buf()]);
-        }
+    pub fn get_size_callback(&self, hash: Hash40) -> Option<crate::api::SizeCallbackFn> {
+        self.size_callbacks.get(&hash).copied()
     }
 
-    pub fn insert_bgm_property_patch(&mut self, hash: Hash40, path: &Path) {
-        if let Some(list) = self.bgm_property_patches.get_mut(&hash) {
-            list.push(path.to_path_buf())
-        } else {
-            self.bgm_property_patches.insert(hash, vec![path.to_path_buf()]);
-        }
+    pub fn has_size_callbacks(&self) -> bool {
+        !self.size_callbacks.is_empty()
+    }
+
+    /// Gets the data callback and size of a stream file provided by a plugin
+    pub fn get_stream_data_callback(&self, hash: Hash40) -> Option<(crate::api::StreamDataCallbackFn, usize)> {
+        let entry = unsafe { &*self.function_map.get(&hash)?.get() };
+
+        entry.functions.iter().find_map(|(_, callback)| match callback {
+            ApiCallback::StreamDataCallback(cb, size) => Some((*cb, *size)),
+            _ => None,
+        })
     }
 
     fn get_stream_cb_path(&self, local: &Path) -> Option<String> {
@@ -674,10 +441,10 @@ impl FileLoader for ApiLoader {
         if let Some(sz) = unsafe { (*self.stream_size_map.get()).get(local_path) } {
             return Some(*sz);
         }
-        if let Some((root_path, _)) = self.use_virtual_file(local_path) {
+        if let Some((root_path, callback)) = self.use_virtual_file(local_path) {
             let result = ApiLoadType::from_root(root_path)
                 .ok()
-                .and_then(|x| x.get_file_size(local_path))
+                .and_then(|x| x.get_file_size(local_path, callback))
                 .or_else(|| self.get_file_size(root_path, local_path));
             self.release_virtual_file(local_path);
             result
@@ -721,6 +488,8 @@ impl FileLoader for ApiLoader {
     fn get_actual_path(&self, root_path: &Path, local_path: &Path) -> Option<PathBuf> {
         if root_path.ends_with("stream-cb") {
             Some(self.get_stream_cb_path(local_path).map_or(root_path.join(local_path), PathBuf::from))
+        } else if root_path.ends_with("stream-data-cb") {
+            local_path.smash_hash().ok().map(crate::fuse::stream::virtual_path)
         } else {
             Some(root_path.join(local_path))
         }
@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use thiserror::Error;
+
+pub mod bgm_property;
+pub mod motionlist;
+pub mod msbt;
+pub mod nus3audio;
+pub mod nus3bank;
+pub mod prc;
+
+/// A file format that mods can patch instead of replacing the whole file.
+///
+/// Every patch file found during discovery is mapped to the file it targets, and the handler is asked to build that file
+/// out of its base data and all of the patch files targeting it when the game loads it.
+pub trait PatchHandler: Send + Sync {
+    /// Unique name of the format. Patched files of this format live under `api:/patch-<name>` in the API tree.
+    fn name(&self) -> &str;
+
+    /// Endings of the patch file names, either an extension or a whole file name like `motion_list.yml`.
+    /// Used to collect the patch files during discovery, so two formats cannot claim the same files.
+    fn extensions(&self) -> &[&str];
+
+    /// Checks if a discovered file is a patch file of this format
+    fn is_patch_file(&self, local: &Path) -> bool {
+        local
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| self.extensions().iter().any(|ext| has_name_ending(name, ext)))
+    }
+
+    /// Maps a patch file to the path of the file it patches. The regional suffix is removed afterwards.
+    fn target_path(&self, local: &Path) -> PathBuf;
+
+    /// Builds the patched file from the base data and every patch file targeting it, from the lowest priority mod to the highest
+    fn patch(&self, local: &Path, base: Vec<u8>, patches: &[PatchFile]) -> Result<Vec<u8>, String>;
+
+    /// Whether files of this format can be added to the API tree without any patch file, in which case the vanilla data is served
+    fn serves_vanilla(&self) -> bool {
+        false
+    }
+
+    /// The root of the patched files of this format in the API tree
+    fn api_root(&self) -> PathBuf {
+        PathBuf::from(format!("api:/patch-{}", self.name()))
+    }
+}
+
+static PATCH_HANDLERS: Lazy<RwLock<Vec<Arc<dyn PatchHandler>>>> = Lazy::new(|| {
+    RwLock::new(vec![
+        Arc::new(prc::PrcHandler),
+        Arc::new(msbt::MsbtHandler),
+        Arc::new(nus3audio::Nus3audioHandler),
+        Arc::new(nus3bank::Nus3bankHandler),
+        Arc::new(motionlist::MotionlistHandler),
+        Arc::new(bgm_property::BgmPropertyHandler),
+    ])
+});
+
+/// Checks if a file name is `ending`, or ends with `.` followed by `ending`
+fn has_name_ending(name: &str, ending: &str) -> bool {
+    name == ending || name.strip_suffix(ending).map_or(false, |rest| rest.ends_with('.'))
+}
+
+#[derive(Debug, Error)]
+pub enum RegisterHandlerError {
+    #[error("a patch format named '{0}' already exists")]
+    NameTaken(String),
+
+    #[error("'{ending}' files would overlap with the '{existing}' files of the {format} patch format")]
+    EndingTaken { ending: String, format: String, existing: String },
+}
+
+/// Adds a new patch format. Fails if a format with the same name already exists, or if it would claim files of an existing format.
+pub fn register_handler(handler: Arc<dyn PatchHandler>) -> Result<(), RegisterHandlerError> {
+    let mut handlers = PATCH_HANDLERS.write();
+
+    for existing in handlers.iter() {
+        if existing.name() == handler.name() {
+            return Err(RegisterHandlerError::NameTaken(handler.name().to_string()));
+        }
+
+        for ext in existing.extensions().iter() {
+            if let Some(ending) = handler.extensions().iter().find(|other| has_name_ending(ext, other) || has_name_ending(other, ext)) {
+                return Err(RegisterHandlerError::EndingTaken {
+                    ending: ending.to_string(),
+                    format: existing.name().to_string(),
+                    existing: ext.to_string(),
+                });
+            }
+        }
+    }
+
+    handlers.push(handler);
+    Ok(())
+}
+
+/// Finds the format a discovered file is a patch file of
+pub fn handler_for_file(local: &Path) -> Option<Arc<dyn PatchHandler>> {
+    PATCH_HANDLERS.read().iter().find(|handler| handler.is_patch_file(local)).cloned()
+}
+
+/// Finds a format by its name
+pub fn handler_by_name(name: &str) -> Option<Arc<dyn PatchHandler>> {
+    PATCH_HANDLERS.read().iter().find(|handler| handler.name() == name).cloned()
+}
+
+/// Checks if a file name ends like the patch files of any known format
+pub fn has_patch_extension(name: &str) -> bool {
+    PATCH_HANDLERS
+        .read()
+        .iter()
+        .any(|handler| handler.extensions().iter().any(|ext| has_name_ending(name, ext)))
+}
+
+// The priority of every enabled mod root, as decided during discovery. The higher it is, the later its patch files are applied.
+static MOD_PRIORITIES: Lazy<RwLock<HashMap<PathBuf, i32>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Remembers the priority of the enabled mod roots, given from the highest priority to the lowest like they are discovered
+pub fn set_mod_priorities(roots: &[PathBuf]) {
+    *MOD_PRIORITIES.write() = roots.iter().rev().enumerate().map(|(rank, root)| (root.clone(), rank as i32)).collect();
+}
+
+/// Gets the priority of a mod root in the workspace, which is the same one that decides file conflicts during discovery.
+/// When two mods patch the same entry differently, the one with the highest priority wins.
+pub fn mod_priority(root: &Path) -> i32 {
+    MOD_PRIORITIES.read().get(root).copied().unwrap_or(-1)
+}
+
+/// A patch file collected during discovery, along with the mod root that provided it
+#[derive(Debug, Clone)]
+pub struct PatchFile {
+    pub root: PathBuf,
+    pub path: PathBuf,
+    pub priority: i32,
+}
+
+impl PatchFile {
+    pub fn new(root: &Path, path: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            path: path.to_path_buf(),
+            priority: mod_priority(root),
+        }
+    }
+}
+
+/// Inserts a patch file in a list sorted from the lowest priority to the highest, so that the highest priority mod is applied last.
+/// Patch files of the same priority stay in the order they were inserted.
+pub fn insert_by_priority(list: &mut Vec<PatchFile>, patch: PatchFile) {
+    let index = list.partition_point(|other| other.priority <= patch.priority);
+    list.insert(index, patch);
+}
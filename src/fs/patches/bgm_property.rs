@@ -0,0 +1,480 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use hash40::Hash40;
+use serde::{
+    de::{self, Unexpected, Visitor},
+    Deserialize, Deserializer,
+};
+use smash_bgm_property::{BgmPropertyEntry, BgmPropertyFile};
+
+use super::{PatchFile, PatchHandler};
+use crate::PathExtension;
+
+/// A set of changes to a single bgm_property entry, keyed by the stream name.
+/// Fields which are `None` are left untouched on the base entry.
+///
+/// This is also the format of the entries in a `bgm_property.yml` patch file:
+/// ```yaml
+/// - stream_name: bgm_z90_my_song
+///   loop_start_ms: 1500
+///   loop_start_sample: 72000
+/// - stream_name: 0x12f6c8a3b4
+///   remove: true
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct EntryPatch {
+    #[serde(deserialize_with = "deserialize_stream_name")]
+    pub stream_name: Hash40,
+    pub loop_start_ms: Option<u32>,
+    pub loop_start_sample: Option<u32>,
+    pub loop_end_ms: Option<u32>,
+    pub loop_end_sample: Option<u32>,
+    pub duration_ms: Option<u32>,
+    pub duration_sample: Option<u32>,
+    #[serde(default)]
+    pub remove: bool,
+}
+
+/// Accepts either the name of the stream or its hash, written as a number or as a hexadecimal string.
+/// YAML reads an unquoted `0x12f6c8a3b4` as a number, so both forms have to be handled.
+fn deserialize_stream_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Hash40, D::Error> {
+    struct StreamNameVisitor;
+
+    impl<'de> Visitor<'de> for StreamNameVisitor {
+        type Value = Hash40;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a stream name or its hash")
+        }
+
+        fn visit_u64<E: de::Error>(self, hash: u64) -> Result<Hash40, E> {
+            Ok(Hash40(hash))
+        }
+
+        fn visit_i64<E: de::Error>(self, hash: i64) -> Result<Hash40, E> {
+            u64::try_from(hash)
+                .map(Hash40)
+                .map_err(|_| E::invalid_value(Unexpected::Signed(hash), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, name: &str) -> Result<Hash40, E> {
+            if let Some(hex) = name.strip_prefix("0x") {
+                u64::from_str_radix(hex, 16).map(Hash40).map_err(E::custom)
+            } else {
+                Ok(hash40::hash40(name))
+            }
+        }
+    }
+
+    deserializer.deserialize_any(StreamNameVisitor)
+}
+
+impl EntryPatch {
+    fn empty(stream_name: Hash40) -> Self {
+        Self {
+            stream_name,
+            loop_start_ms: None,
+            loop_start_sample: None,
+            loop_end_ms: None,
+            loop_end_sample: None,
+            duration_ms: None,
+            duration_sample: None,
+            remove: false,
+        }
+    }
+
+    /// Builds a patch out of an entry found in a full bgm_property.bin, only keeping the fields that differ from the base entry.
+    /// An entry with every field zeroed out is considered a request to remove the stream from the file.
+    pub fn from_entry(base: Option<&BgmPropertyEntry>, entry: &BgmPropertyEntry) -> Self {
+        let mut patch = Self::empty(entry.stream_name);
+
+        if entry.loop_start_ms == 0
+            && entry.loop_start_sample == 0
+            && entry.loop_end_ms == 0
+            && entry.loop_end_sample == 0
+            && entry.duration_ms == 0
+            && entry.duration_sample == 0
+        {
+            patch.remove = true;
+            return patch;
+        }
+
+        macro_rules! diff_field {
+            ($field:ident) => {
+                if base.map_or(true, |base| base.$field != entry.$field) {
+                    patch.$field = Some(entry.$field);
+                }
+            };
+        }
+
+        diff_field!(loop_start_ms);
+        diff_field!(loop_start_sample);
+        diff_field!(loop_end_ms);
+        diff_field!(loop_end_sample);
+        diff_field!(duration_ms);
+        diff_field!(duration_sample);
+
+        patch
+    }
+
+    /// Returns true if the patch would not change anything
+    pub fn is_empty(&self) -> bool {
+        !self.remove
+            && self.loop_start_ms.is_none()
+            && self.loop_start_sample.is_none()
+            && self.loop_end_ms.is_none()
+            && self.loop_end_sample.is_none()
+            && self.duration_ms.is_none()
+            && self.duration_sample.is_none()
+    }
+
+    /// Checks if both patches disagree on the value of at least one field
+    pub fn conflicts_with(&self, other: &Self) -> bool {
+        macro_rules! field_conflicts {
+            ($field:ident) => {
+                matches!((self.$field, other.$field), (Some(a), Some(b)) if a != b)
+            };
+        }
+
+        self.remove != other.remove
+            || field_conflicts!(loop_start_ms)
+            || field_conflicts!(loop_start_sample)
+            || field_conflicts!(loop_end_ms)
+            || field_conflicts!(loop_end_sample)
+            || field_conflicts!(duration_ms)
+            || field_conflicts!(duration_sample)
+    }
+
+    /// Layers another patch on top of this one. Fields set by `other` take priority.
+    pub fn merge(&mut self, other: &Self) {
+        macro_rules! merge_field {
+            ($field:ident) => {
+                if other.$field.is_some() {
+                    self.$field = other.$field;
+                }
+            };
+        }
+
+        merge_field!(loop_start_ms);
+        merge_field!(loop_start_sample);
+        merge_field!(loop_end_ms);
+        merge_field!(loop_end_sample);
+        merge_field!(duration_ms);
+        merge_field!(duration_sample);
+        self.remove = other.remove;
+    }
+
+    pub fn apply(&self, entry: &mut BgmPropertyEntry) {
+        macro_rules! apply_field {
+            ($field:ident) => {
+                if let Some(value) = self.$field {
+                    entry.$field = value;
+                }
+            };
+        }
+
+        apply_field!(loop_start_ms);
+        apply_field!(loop_start_sample);
+        apply_field!(loop_end_ms);
+        apply_field!(loop_end_sample);
+        apply_field!(duration_ms);
+        apply_field!(duration_sample);
+    }
+
+    /// Creates a brand new entry out of the patch, using 0 for every field that was not provided
+    pub fn to_entry(&self) -> BgmPropertyEntry {
+        BgmPropertyEntry {
+            stream_name: self.stream_name,
+            loop_start_ms: self.loop_start_ms.unwrap_or_default(),
+            loop_start_sample: self.loop_start_sample.unwrap_or_default(),
+            loop_end_ms: self.loop_end_ms.unwrap_or_default(),
+            loop_end_sample: self.loop_end_sample.unwrap_or_default(),
+            duration_ms: self.duration_ms.unwrap_or_default(),
+            duration_sample: self.duration_sample.unwrap_or_default(),
+        }
+    }
+}
+
+/// Merges bgm_property patches from every mod onto a base file, entry by entry.
+pub struct BgmPropertyMerger {
+    base: BgmPropertyFile,
+    // The merged patch for every stream, along with the mod roots that contributed to it
+    patches: HashMap<Hash40, (EntryPatch, Vec<PathBuf>)>,
+    // Keeps track of the order in which the streams were first patched, so that new entries are appended deterministically
+    order: Vec<Hash40>,
+}
+
+impl BgmPropertyMerger {
+    pub fn new(base: BgmPropertyFile) -> Self {
+        Self {
+            base,
+            patches: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Reads a full bgm_property.bin provided by a mod and registers the differences with the base file
+    pub fn add_binary_patch(&mut self, patch: &PatchFile) -> Result<(), String> {
+        let file = BgmPropertyFile::from_file(&patch.path).map_err(|e| format!("{:?}", e))?;
+
+        let patches: Vec<EntryPatch> = file
+            .entries
+            .iter()
+            .map(|entry| {
+                let base = self.base.entries.iter().find(|base| base.stream_name == entry.stream_name);
+                EntryPatch::from_entry(base, entry)
+            })
+            .collect();
+
+        for entry_patch in patches {
+            self.add_entry_patch(&patch.root, entry_patch);
+        }
+
+        Ok(())
+    }
+
+    /// Reads a `bgm_property.yml` file provided by a mod, which only lists the entries it changes
+    pub fn add_text_patch(&mut self, patch: &PatchFile) -> Result<(), String> {
+        let contents = std::fs::read_to_string(&patch.path).map_err(|e| format!("{:?}", e))?;
+        let entries: Vec<EntryPatch> = serde_yaml::from_str(&contents).map_err(|e| format!("{}", e))?;
+
+        for entry_patch in entries {
+            self.add_entry_patch(&patch.root, entry_patch);
+        }
+
+        Ok(())
+    }
+
+    /// Registers the changes a mod makes to a single entry, reporting conflicts with mods that edited the same entry before it
+    pub fn add_entry_patch(&mut self, root: &Path, patch: EntryPatch) {
+        if patch.is_empty() {
+            return;
+        }
+
+        match self.patches.get_mut(&patch.stream_name) {
+            Some((current, roots)) => {
+                if current.conflicts_with(&patch) {
+                    for other in roots.iter().filter(|other| other.as_path() != root) {
+                        warn!(
+                            "bgm_property entry {:#x} is edited by both '{}' and '{}'. The changes from '{}' take priority.",
+                            patch.stream_name.0,
+                            other.display(),
+                            root.display(),
+                            root.display()
+                        );
+                    }
+                }
+
+                current.merge(&patch);
+
+                if !roots.iter().any(|other| other == root) {
+                    roots.push(root.to_path_buf());
+                }
+            },
+            None => {
+                self.order.push(patch.stream_name);
+                self.patches.insert(patch.stream_name, (patch, vec![root.to_path_buf()]));
+            },
+        }
+    }
+
+    /// Applies every registered patch and returns the resulting file
+    pub fn finish(mut self) -> BgmPropertyFile {
+        let mut entries = Vec::with_capacity(self.base.entries.len());
+
+        for mut entry in std::mem::take(&mut self.base.entries) {
+            match self.patches.remove(&entry.stream_name) {
+                Some((patch, _)) if patch.remove => {},
+                Some((patch, _)) => {
+                    patch.apply(&mut entry);
+                    entries.push(entry);
+                },
+                None => entries.push(entry),
+            }
+        }
+
+        // Whatever is left was not part of the base file, so append it
+        for stream_name in self.order {
+            if let Some((patch, _)) = self.patches.remove(&stream_name) {
+                if !patch.remove {
+                    entries.push(patch.to_entry());
+                }
+            }
+        }
+
+        self.base.entries = entries;
+        self.base
+    }
+}
+
+/// Merges full bgm_property.bin files and bgm_property.yml patches onto the bgm_property.bin of the game
+pub struct BgmPropertyHandler;
+
+impl PatchHandler for BgmPropertyHandler {
+    fn name(&self) -> &str {
+        "bgm_property"
+    }
+
+    // Whole file names rather than extensions, since every other .bin and .yml file is a regular mod file
+    fn extensions(&self) -> &[&str] {
+        &["bgm_property.bin", "bgm_property.yml"]
+    }
+
+    fn target_path(&self, local: &Path) -> PathBuf {
+        local.with_extension("bin")
+    }
+
+    fn patch(&self, _local: &Path, base: Vec<u8>, patches: &[PatchFile]) -> Result<Vec<u8>, String> {
+        let mut reader = Cursor::new(&base[..]);
+        let bgm_property = BgmPropertyFile::read(&mut reader).map_err(|e| format!("Unable to parse bgm_property data! {:?}", e))?;
+
+        let mut merger = BgmPropertyMerger::new(bgm_property);
+
+        for patch in patches.iter() {
+            let result = if patch.path.has_extension("yml") {
+                merger.add_text_patch(patch)
+            } else {
+                merger.add_binary_patch(patch)
+            };
+
+            if let Err(e) = result {
+                warn!("bgm_property patch `{}` could not be read, skipping. Reason: {}", patch.path.display(), e);
+            }
+        }
+
+        let mut writer = Cursor::new(Vec::new());
+        merger.finish().write(&mut writer).map_err(|e| format!("{:?}", e))?;
+        Ok(writer.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(stream_name: &str, values: [u32; 6]) -> BgmPropertyEntry {
+        BgmPropertyEntry {
+            stream_name: hash40::hash40(stream_name),
+            loop_start_ms: values[0],
+            loop_start_sample: values[1],
+            loop_end_ms: values[2],
+            loop_end_sample: values[3],
+            duration_ms: values[4],
+            duration_sample: values[5],
+        }
+    }
+
+    fn values(entry: &BgmPropertyEntry) -> [u32; 6] {
+        [
+            entry.loop_start_ms,
+            entry.loop_start_sample,
+            entry.loop_end_ms,
+            entry.loop_end_sample,
+            entry.duration_ms,
+            entry.duration_sample,
+        ]
+    }
+
+    #[test]
+    fn only_keeps_the_fields_that_differ_from_the_base() {
+        let base = entry("bgm_a", [1, 2, 3, 4, 5, 6]);
+        let patch = EntryPatch::from_entry(Some(&base), &entry("bgm_a", [1, 20, 3, 4, 50, 6]));
+
+        let mut expected = EntryPatch::empty(hash40::hash40("bgm_a"));
+        expected.loop_start_sample = Some(20);
+        expected.duration_ms = Some(50);
+
+        assert_eq!(patch, expected);
+    }
+
+    #[test]
+    fn unchanged_entries_make_empty_patches() {
+        let base = entry("bgm_a", [1, 2, 3, 4, 5, 6]);
+
+        assert!(EntryPatch::from_entry(Some(&base), &base).is_empty());
+    }
+
+    #[test]
+    fn new_entries_keep_every_field() {
+        let patch = EntryPatch::from_entry(None, &entry("bgm_new", [1, 2, 3, 4, 5, 6]));
+
+        assert!(!patch.remove);
+        assert_eq!(values(&patch.to_entry()), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn zeroed_entries_are_removals() {
+        let base = entry("bgm_a", [1, 2, 3, 4, 5, 6]);
+        let patch = EntryPatch::from_entry(Some(&base), &entry("bgm_a", [0; 6]));
+
+        assert!(patch.remove);
+        assert!(!patch.is_empty());
+    }
+
+    #[test]
+    fn patches_only_conflict_on_different_values() {
+        let base = entry("bgm_a", [1, 2, 3, 4, 5, 6]);
+        let first = EntryPatch::from_entry(Some(&base), &entry("bgm_a", [10, 2, 3, 4, 5, 6]));
+        let same = EntryPatch::from_entry(Some(&base), &entry("bgm_a", [10, 2, 30, 4, 5, 6]));
+        let different = EntryPatch::from_entry(Some(&base), &entry("bgm_a", [11, 2, 3, 4, 5, 6]));
+        let removal = EntryPatch::from_entry(Some(&base), &entry("bgm_a", [0; 6]));
+
+        assert!(!first.conflicts_with(&same));
+        assert!(first.conflicts_with(&different));
+        assert!(first.conflicts_with(&removal));
+    }
+
+    #[test]
+    fn merged_patches_apply_every_change_with_the_latest_winning() {
+        let base = entry("bgm_a", [1, 2, 3, 4, 5, 6]);
+        let mut patch = EntryPatch::from_entry(Some(&base), &entry("bgm_a", [10, 2, 30, 4, 5, 6]));
+        patch.merge(&EntryPatch::from_entry(Some(&base), &entry("bgm_a", [1, 2, 31, 4, 5, 60])));
+
+        let mut patched = entry("bgm_a", [1, 2, 3, 4, 5, 6]);
+        patch.apply(&mut patched);
+
+        assert_eq!(values(&patched), [10, 2, 31, 4, 5, 60]);
+    }
+
+    fn parse(yaml: &str) -> Result<Vec<EntryPatch>, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    #[test]
+    fn reads_stream_names_and_hashes() {
+        let hash = hash40::hash40("bgm_a");
+        let yaml = format!(
+            "- stream_name: bgm_a\n- stream_name: \"{:#x}\"\n- stream_name: {:#x}\n- stream_name: {}\n",
+            hash.0, hash.0, hash.0
+        );
+
+        let patches = parse(&yaml).unwrap();
+
+        assert_eq!(patches.len(), 4);
+        assert!(patches.iter().all(|patch| patch.stream_name == hash));
+    }
+
+    #[test]
+    fn rejects_invalid_stream_hashes() {
+        assert!(parse("- stream_name: -1\n").is_err());
+        assert!(parse("- stream_name: \"0xnothex\"\n").is_err());
+    }
+
+    #[test]
+    fn only_lists_the_changed_fields() {
+        let patches = parse("- stream_name: bgm_a\n  loop_start_ms: 1500\n- stream_name: bgm_b\n  remove: true\n").unwrap();
+
+        let mut expected = EntryPatch::empty(hash40::hash40("bgm_a"));
+        expected.loop_start_ms = Some(1500);
+
+        assert_eq!(patches[0], expected);
+        assert!(patches[1].remove);
+        assert!(parse("- stream_name: bgm_c\n").unwrap()[0].is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use hash40::diff::Diff;
+
+use super::{PatchFile, PatchHandler};
+use crate::PathExtension;
+
+/// Applies motdiff patches and full motion_list.yml replacements onto motion_list.bin files
+pub struct MotionlistHandler;
+
+impl PatchHandler for MotionlistHandler {
+    fn name(&self) -> &str {
+        "motionlist"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["motdiff", "motion_list.yml"]
+    }
+
+    fn target_path(&self, local: &Path) -> PathBuf {
+        local.with_extension("bin")
+    }
+
+    fn patch(&self, local: &Path, base: Vec<u8>, patches: &[PatchFile]) -> Result<Vec<u8>, String> {
+        let mut yml_patches = Vec::new();
+        let mut diff_patches = Vec::new();
+
+        for patch in patches.iter() {
+            if patch.path.has_extension("motdiff") {
+                diff_patches.push(&patch.path);
+            } else if patch.path.ends_with("motion_list.yml") {
+                yml_patches.push(&patch.path);
+            } else {
+                return Err("This isn't a motion list patch file!".to_string());
+            }
+        }
+
+        let mut reader = Cursor::new(base);
+        let mut motion_list = motion_lib::read_stream(&mut reader).map_err(|e| format!("{:?}", e))?;
+
+        if !yml_patches.is_empty() {
+            println!("[ARCropolis::loader] motion_list.yml file(s) found!");
+            let mut full_patches = 0;
+
+            for full_patch in yml_patches.iter() {
+                println!("[ARCropolis::loader] Replacing motion_list.bin with {}.", full_patch.display());
+                let contents = std::fs::read_to_string(full_patch).map_err(|e| format!("{:?}", e))?;
+                if let Some(full) = serde_yaml::from_str(&contents).map_err(|e| format!("{}", e))? {
+                    motion_list = full;
+                    full_patches += 1;
+                }
+            }
+
+            if full_patches > 1 {
+                println!("[ARCropolis::loader] Multiple motion_list.yml files found for {}.", local.display());
+                println!("                     The last applied .yml file will be used.");
+            }
+        }
+
+        for patch_path in diff_patches.iter() {
+            let contents = std::fs::read_to_string(patch_path).map_err(|e| format!("{:?}", e))?;
+            if let Some(diff) = serde_yaml::from_str(&contents).map_err(|e| format!("{}", e))? {
+                motion_list.apply(&diff);
+            } else {
+                return Err("This isn't a motion list patch file!".to_string());
+            }
+        }
+
+        println!("[ARCropolis::loader] 'motion_list.bin' patching finished!");
+        let mut writer = Cursor::new(Vec::new());
+        motion_lib::write_stream(&mut writer, &motion_list).map_err(|e| format!("{:?}", e))?;
+        Ok(writer.into_inner())
+    }
+}
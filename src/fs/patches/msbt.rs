@@ -0,0 +1,334 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine};
+use msbt::{builder::MsbtBuilder, Msbt};
+use serde::Deserialize;
+use xml::common::Position;
+
+use super::{PatchFile, PatchHandler};
+use crate::fs::conflicts;
+
+mod raw;
+use raw::RawMsbt;
+
+#[derive(Debug, Deserialize)]
+pub struct Xmsbt {
+    #[serde(rename = "entry")]
+    entries: Vec<Entry>,
+}
+
+/// A single label of a XMSBT file. Everything but the label is optional, so that a mod only has to provide what it changes:
+/// ```xml
+/// <entry label="nam_chr1_00_mario" style="1" attribute="AAAAAA==">
+///     <text>Mario</text>
+/// </entry>
+/// <entry label="nam_chr1_00_luigi" remove="true"/>
+/// ```
+/// `attribute` is the base64 encoded ATR1 data of the message, and `style` its TSY1 style index.
+#[derive(Debug, Deserialize)]
+pub struct Entry {
+    label: String,
+    base64: Option<bool>,
+    attribute: Option<String>,
+    style: Option<u32>,
+    remove: Option<bool>,
+    #[serde(rename = "text")]
+    text: Option<Text>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Text {
+    #[serde(rename = "$value")]
+    value: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TextType {
+    Text(String),
+    Data(Vec<u8>),
+}
+
+impl TextType {
+    /// Gets the raw bytes to store in the MSBT for this text
+    fn to_raw(&self) -> Vec<u8> {
+        match self {
+            TextType::Text(text) => {
+                let mut raw: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+                raw.extend_from_slice(&[0, 0]);
+                raw
+            },
+            TextType::Data(data) => data.clone(),
+        }
+    }
+}
+
+/// Every change a mod makes to a single label
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LabelPatch {
+    text: Option<TextType>,
+    attribute: Option<Vec<u8>>,
+    style: Option<u32>,
+    remove: bool,
+}
+
+impl LabelPatch {
+    /// Checks if both patches disagree on the value of at least one field
+    fn conflicts_with(&self, other: &Self) -> bool {
+        fn field_conflicts<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+            matches!((a, b), (Some(a), Some(b)) if a != b)
+        }
+
+        self.remove != other.remove
+            || field_conflicts(&self.text, &other.text)
+            || field_conflicts(&self.attribute, &other.attribute)
+            || field_conflicts(&self.style, &other.style)
+    }
+
+    /// Layers another patch on top of this one. Fields set by `other` take priority.
+    fn merge(&mut self, other: Self) {
+        if other.text.is_some() {
+            self.text = other.text;
+        }
+
+        if other.attribute.is_some() {
+            self.attribute = other.attribute;
+        }
+
+        if other.style.is_some() {
+            self.style = other.style;
+        }
+
+        self.remove = other.remove;
+    }
+}
+
+/// Merges XMSBT files onto a base MSBT, keeping track of the mod that provided every label
+pub struct MsbtMerger {
+    local: PathBuf,
+    labels: HashMap<String, (LabelPatch, PathBuf)>,
+    conflicts: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl MsbtMerger {
+    pub fn new(local: &Path) -> Self {
+        Self {
+            local: local.to_path_buf(),
+            labels: HashMap::new(),
+            conflicts: HashMap::new(),
+        }
+    }
+
+    fn set_label(&mut self, label: &str, patch: LabelPatch, root: &Path) {
+        match self.labels.get_mut(label) {
+            Some((previous, previous_root)) => {
+                if previous_root != root && previous.conflicts_with(&patch) {
+                    warn!(
+                        "XMSBT label '{}' in '{}' is set differently by '{}' and '{}'. '{}' takes priority.",
+                        label,
+                        self.local.display(),
+                        previous_root.display(),
+                        root.display(),
+                        root.display()
+                    );
+
+                    let key = PathBuf::from(format!("{}:{}", self.local.display(), label));
+                    let roots = self.conflicts.entry(key).or_default();
+
+                    for conflicting in [previous_root.as_path(), root] {
+                        if !roots.iter().any(|known| known == conflicting) {
+                            roots.push(conflicting.to_path_buf());
+                        }
+                    }
+                }
+
+                previous.merge(patch);
+                *previous_root = root.to_path_buf();
+            },
+            None => {
+                self.labels.insert(label.to_string(), (patch, root.to_path_buf()));
+            },
+        }
+    }
+
+    /// Reads a XMSBT file and registers every label it provides. Malformed files are skipped.
+    pub fn add_patch(&mut self, patch: &PatchFile) -> Result<(), std::io::Error> {
+        let mut reader = Cursor::new(std::fs::read(&patch.path)?);
+        let xmsbt: Xmsbt = match serde_xml_rs::from_reader(&mut reader) {
+            Ok(xmsbt) => xmsbt,
+            Err(err) => {
+                match err {
+                    serde_xml_rs::Error::Syntax { source } => {
+                        let position = source.position();
+                        warn!(
+                            "XMSBT file `{}` could not be read due to the following syntax error at line {}, column {}: `{}`, skipping.",
+                            patch.path.display(),
+                            position.row + 1,
+                            position.column,
+                            source.msg()
+                        )
+                    },
+                    _ => warn!("XMSBT file `{}` is malformed, skipping.", patch.path.display()),
+                }
+
+                return Ok(());
+            },
+        };
+
+        for entry in &xmsbt.entries {
+            let mut label_patch = LabelPatch {
+                style: entry.style,
+                remove: entry.remove.unwrap_or(false),
+                ..Default::default()
+            };
+
+            if let Some(text) = entry.text.as_ref() {
+                if entry.base64.unwrap_or(false) {
+                    match BASE64_STANDARD.decode::<String>(text.value.to_owned()) {
+                        Ok(mut decoded) => {
+                            // Pushing these 0s to ensure that the end of the text is marked clearly
+                            decoded.push(0);
+                            decoded.push(0);
+                            label_patch.text = Some(TextType::Data(decoded));
+                        },
+                        Err(err) => error!("XMSBT Label {} could not be base64 decoded. Reason: {}", entry.label, err),
+                    }
+                } else {
+                    label_patch.text = Some(TextType::Text(text.value.to_owned()));
+                }
+            }
+
+            if let Some(attribute) = entry.attribute.as_ref() {
+                match BASE64_STANDARD.decode::<&str>(attribute) {
+                    Ok(decoded) => label_patch.attribute = Some(decoded),
+                    Err(err) => error!("XMSBT Label {} attribute could not be base64 decoded. Reason: {}", entry.label, err),
+                }
+            }
+
+            self.set_label(&entry.label, label_patch, &patch.root);
+        }
+
+        Ok(())
+    }
+
+    /// Applies every label onto the base MSBT and returns the new file
+    pub fn finish(self, data: &[u8]) -> Result<Vec<u8>, String> {
+        if !self.conflicts.is_empty() {
+            if let Err(e) = conflicts::extend(&self.conflicts) {
+                error!("{}", e);
+            }
+        }
+
+        let mut msbt = Msbt::from_reader(Cursor::new(data)).map_err(|e| format!("{:?}", e))?;
+
+        let mut existing = HashSet::new();
+
+        for lbl in msbt.lbl1_mut().ok_or("MSBT does not have a LBL1 section")?.labels_mut() {
+            let lbl_name = lbl.name().to_owned();
+
+            if let Some((patch, _)) = self.labels.get(&lbl_name) {
+                if let Some(text) = patch.text.as_ref().filter(|_| !patch.remove) {
+                    lbl.set_value_raw(&text.to_raw()[..]).map_err(|e| format!("{:?}", e))?;
+                }
+            }
+
+            existing.insert(lbl_name);
+        }
+
+        let mut builder = MsbtBuilder::from(msbt);
+
+        for (label, (patch, root)) in self.labels.iter() {
+            if existing.contains(label) || patch.remove {
+                continue;
+            }
+
+            match patch.text.as_ref() {
+                Some(text) => builder = builder.add_label(label.to_owned(), &text.to_raw()[..]),
+                None => warn!(
+                    "XMSBT label '{}' from '{}' does not exist in '{}' and does not provide any text, ignoring.",
+                    label,
+                    root.display(),
+                    self.local.display()
+                ),
+            }
+        }
+
+        let out_msbt = builder.build();
+        let mut cursor = Cursor::new(Vec::new());
+        out_msbt.write_to(&mut cursor).map_err(|e| format!("{:?}", e))?;
+
+        // Only go through the sections ourselves if the msbt crate couldn't handle everything
+        if !self.labels.values().any(|(patch, _)| patch.remove || patch.attribute.is_some() || patch.style.is_some()) {
+            return Ok(cursor.into_inner());
+        }
+
+        let mut raw = RawMsbt::parse(&cursor.into_inner())?;
+
+        for (label, (patch, root)) in self.labels.iter() {
+            if patch.remove {
+                match raw.remove(label) {
+                    Ok(true) => {},
+                    Ok(false) => warn!(
+                        "XMSBT label '{}' from '{}' cannot be removed from '{}' because it does not exist.",
+                        label,
+                        root.display(),
+                        self.local.display()
+                    ),
+                    Err(e) => warn!("Could not remove XMSBT label '{}' from '{}'. Reason: {}", label, root.display(), e),
+                }
+
+                continue;
+            }
+
+            let index = match raw.index_of(label)? {
+                Some(index) => index,
+                None => continue,
+            };
+
+            if let Some(attribute) = patch.attribute.as_ref() {
+                if let Err(e) = raw.set_attribute(index, attribute) {
+                    warn!("Could not set the attribute of XMSBT label '{}' from '{}'. Reason: {}", label, root.display(), e);
+                }
+            }
+
+            if let Some(style) = patch.style {
+                if let Err(e) = raw.set_style(index, style) {
+                    warn!("Could not set the style of XMSBT label '{}' from '{}'. Reason: {}", label, root.display(), e);
+                }
+            }
+        }
+
+        Ok(raw.write())
+    }
+}
+
+/// Merges XMSBT files onto MSBT files
+pub struct MsbtHandler;
+
+impl PatchHandler for MsbtHandler {
+    fn name(&self) -> &str {
+        "msbt"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["xmsbt"]
+    }
+
+    fn target_path(&self, local: &Path) -> PathBuf {
+        local.with_extension("msbt")
+    }
+
+    fn patch(&self, local: &Path, base: Vec<u8>, patches: &[PatchFile]) -> Result<Vec<u8>, String> {
+        // The patches come from the lowest to the highest priority mod, so that the highest one wins any conflicting label
+        let mut merger = MsbtMerger::new(local);
+
+        for patch in patches.iter() {
+            merger.add_patch(patch).map_err(|e| format!("{:?}", e))?;
+        }
+
+        merger.finish(&base)
+    }
+}
@@ -0,0 +1,493 @@
+use std::convert::TryInto;
+
+// MSBT layout, as far as it matters for the edits the msbt crate cannot do:
+// The file starts with a 0x20 bytes header, which holds the amount of sections at 0xE and the size of the file at 0x12.
+// Every section starts with its magic, the size of its data and 8 bytes of padding. Its data is then padded to 0x10 bytes with 0xAB.
+const HEADER_SIZE: usize = 0x20;
+const SECTION_HEADER_SIZE: usize = 0x10;
+const SECTION_ALIGNMENT: usize = 0x10;
+const SECTION_PADDING_BYTE: u8 = 0xAB;
+
+static MSBT_MAGIC: &[u8] = b"MsgStdBn";
+static LITTLE_ENDIAN_BOM: &[u8] = &[0xFF, 0xFE];
+
+static LBL1_MAGIC: &[u8] = b"LBL1";
+static ATR1_MAGIC: &[u8] = b"ATR1";
+static TSY1_MAGIC: &[u8] = b"TSY1";
+static TXT2_MAGIC: &[u8] = b"TXT2";
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| format!("Unexpected end of data at {:#x}", offset))
+}
+
+fn align(offset: usize) -> usize {
+    (offset + (SECTION_ALIGNMENT - 1)) & !(SECTION_ALIGNMENT - 1)
+}
+
+fn label_hash(name: &str, slot_count: u32) -> u32 {
+    name.bytes().fold(0u32, |hash, byte| hash.wrapping_mul(0x492).wrapping_add(byte as u32)) % slot_count
+}
+
+/// Reads the attribute count and size of an ATR1 section, and whether a string table follows the attributes
+fn attribute_layout(data: &[u8]) -> Result<(usize, usize, bool), String> {
+    let count = read_u32(data, 0)? as usize;
+    let entry_size = read_u32(data, 4)? as usize;
+
+    let end = count
+        .checked_mul(entry_size)
+        .and_then(|size| size.checked_add(8))
+        .filter(|end| *end <= data.len())
+        .ok_or("ATR1 section is smaller than its attributes")?;
+
+    Ok((count, entry_size, end < data.len()))
+}
+
+/// Splits a TXT2 section into its messages
+fn read_messages(data: &[u8]) -> Result<Vec<&[u8]>, String> {
+    let count = read_u32(data, 0)? as usize;
+    let offsets = (0..count)
+        .map(|idx| read_u32(data, 4 + idx * 4).map(|x| x as usize))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    offsets
+        .iter()
+        .enumerate()
+        .map(|(idx, start)| {
+            let end = offsets.get(idx + 1).copied().unwrap_or(data.len());
+            data.get(*start..end).ok_or_else(|| format!("TXT2 message {} is out of bounds", idx))
+        })
+        .collect()
+}
+
+struct Section {
+    magic: [u8; 4],
+    data: Vec<u8>,
+}
+
+/// A MSBT split into its sections, to edit the attribute and style of a message or remove it entirely
+pub struct RawMsbt {
+    header: Vec<u8>,
+    sections: Vec<Section>,
+}
+
+impl RawMsbt {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.get(0x0..0x8) != Some(MSBT_MAGIC) {
+            return Err("File is not a valid MSBT".to_string());
+        }
+
+        if data.get(0x8..0xA) != Some(LITTLE_ENDIAN_BOM) {
+            return Err("Only little endian MSBT files are supported".to_string());
+        }
+
+        let section_count = data
+            .get(0xE..0x10)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()) as usize)
+            .ok_or("Unexpected end of file while reading the header")?;
+
+        let mut sections = Vec::with_capacity(section_count);
+        let mut offset = HEADER_SIZE;
+
+        for _ in 0..section_count {
+            let magic: [u8; 4] = data
+                .get(offset..offset + 4)
+                .ok_or("Unexpected end of file while reading sections")?
+                .try_into()
+                .unwrap();
+            let size = read_u32(data, offset + 4)? as usize;
+            let start = offset + SECTION_HEADER_SIZE;
+            let section_data = data.get(start..start + size).ok_or("Unexpected end of file while reading sections")?;
+
+            sections.push(Section {
+                magic,
+                data: section_data.to_vec(),
+            });
+
+            offset = align(start + size);
+        }
+
+        Ok(Self {
+            header: data.get(..HEADER_SIZE).ok_or("Unexpected end of file while reading the header")?.to_vec(),
+            sections,
+        })
+    }
+
+    pub fn write(mut self) -> Vec<u8> {
+        let mut out = std::mem::take(&mut self.header);
+
+        for section in self.sections.iter() {
+            out.extend_from_slice(&section.magic);
+            out.extend_from_slice(&(section.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&[0; 8]);
+            out.extend_from_slice(&section.data);
+            out.resize(align(out.len()), SECTION_PADDING_BYTE);
+        }
+
+        let file_size = out.len() as u32;
+        out[0x12..0x16].copy_from_slice(&file_size.to_le_bytes());
+        out
+    }
+
+    fn section(&self, magic: &[u8]) -> Option<&Section> {
+        self.sections.iter().find(|section| section.magic == magic)
+    }
+
+    fn section_mut(&mut self, magic: &[u8]) -> Option<&mut Section> {
+        self.sections.iter_mut().find(|section| section.magic == magic)
+    }
+
+    /// Reads the hash table slot count and every label with its message index
+    fn labels(&self) -> Result<(u32, Vec<(String, u32)>), String> {
+        let data = &self.section(LBL1_MAGIC).ok_or("MSBT does not have a LBL1 section")?.data;
+        let slot_count = read_u32(data, 0)?;
+
+        if slot_count == 0 {
+            return Err("LBL1 section does not have any hash table slot".to_string());
+        }
+
+        let mut labels = Vec::new();
+
+        for slot in 0..slot_count as usize {
+            let label_count = read_u32(data, 4 + slot * 8)?;
+            let mut offset = read_u32(data, 4 + slot * 8 + 4)? as usize;
+
+            for _ in 0..label_count {
+                let len = *data.get(offset).ok_or("Unexpected end of data while reading labels")? as usize;
+                let name = data
+                    .get(offset + 1..offset + 1 + len)
+                    .ok_or("Unexpected end of data while reading labels")?;
+                let name = String::from_utf8_lossy(name).into_owned();
+                let index = read_u32(data, offset + 1 + len)?;
+                labels.push((name, index));
+                offset += 1 + len + 4;
+            }
+        }
+
+        Ok((slot_count, labels))
+    }
+
+    fn write_labels(&mut self, slot_count: u32, labels: &[(String, u32)]) {
+        let mut slots: Vec<Vec<&(String, u32)>> = vec![Vec::new(); slot_count as usize];
+
+        for label in labels.iter() {
+            slots[label_hash(&label.0, slot_count) as usize].push(label);
+        }
+
+        let mut data = slot_count.to_le_bytes().to_vec();
+        let mut names = Vec::new();
+        let names_start = 4 + slot_count as usize * 8;
+
+        for slot in slots.iter() {
+            data.extend_from_slice(&(slot.len() as u32).to_le_bytes());
+            data.extend_from_slice(&((names_start + names.len()) as u32).to_le_bytes());
+
+            for (name, index) in slot.iter() {
+                names.push(name.len() as u8);
+                names.extend_from_slice(name.as_bytes());
+                names.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+
+        data.extend_from_slice(&names);
+
+        if let Some(section) = self.section_mut(LBL1_MAGIC) {
+            section.data = data;
+        }
+    }
+
+    /// Gets the message index of a label
+    pub fn index_of(&self, label: &str) -> Result<Option<u32>, String> {
+        Ok(self.labels()?.1.into_iter().find(|(name, _)| name == label).map(|(_, index)| index))
+    }
+
+    /// Overwrites the attribute of a message. The data has to match the size of the attributes in the file.
+    pub fn set_attribute(&mut self, index: u32, attribute: &[u8]) -> Result<(), String> {
+        let section = self.section_mut(ATR1_MAGIC).ok_or("MSBT does not have an ATR1 section")?;
+        let (count, entry_size, has_strings) = attribute_layout(&section.data)?;
+
+        if attribute.len() != entry_size {
+            return Err(format!(
+                "Attribute is {:#x} bytes long but the attributes of this MSBT are {:#x} bytes long",
+                attribute.len(),
+                entry_size
+            ));
+        }
+
+        let index = index as usize;
+
+        // Messages added by the builder might not have an attribute yet
+        if index >= count {
+            // Growing the attributes would move the string table without updating the offsets pointing into it
+            if has_strings {
+                return Err("Cannot add an attribute to a MSBT whose ATR1 section has a string table".to_string());
+            }
+
+            let end = 8 + count * entry_size;
+            let missing = vec![0; (index + 1 - count) * entry_size];
+            section.data.splice(end..end, missing);
+            section.data[0..4].copy_from_slice(&((index + 1) as u32).to_le_bytes());
+        }
+
+        let start = 8 + index * entry_size;
+        section.data[start..start + entry_size].copy_from_slice(attribute);
+        Ok(())
+    }
+
+    /// Overwrites the style index of a message
+    pub fn set_style(&mut self, index: u32, style: u32) -> Result<(), String> {
+        let section = self.section_mut(TSY1_MAGIC).ok_or("MSBT does not have a TSY1 section")?;
+        let start = index as usize * 4;
+
+        // Messages added by the builder might not have a style yet
+        if section.data.len() < start + 4 {
+            section.data.resize(start + 4, 0);
+        }
+
+        section.data[start..start + 4].copy_from_slice(&style.to_le_bytes());
+        Ok(())
+    }
+
+    /// Removes a label along with its message, attribute and style
+    pub fn remove(&mut self, label: &str) -> Result<bool, String> {
+        let (slot_count, mut labels) = self.labels()?;
+
+        let index = match labels.iter().position(|(name, _)| name == label) {
+            Some(position) => labels.remove(position).1,
+            None => return Ok(false),
+        };
+
+        // Everything is read before anything is changed, so that a MSBT that cannot be edited is left as it was
+        let messages: Option<Vec<Vec<u8>>> = match self.section(TXT2_MAGIC) {
+            Some(section) => Some(read_messages(&section.data)?.into_iter().map(<[u8]>::to_vec).collect()),
+            None => None,
+        };
+
+        let attributes = match self.section(ATR1_MAGIC) {
+            Some(section) => {
+                let (count, entry_size, has_strings) = attribute_layout(&section.data)?;
+
+                // Removing an attribute would move the string table without updating the offsets pointing into it
+                if has_strings {
+                    return Err(format!(
+                        "Cannot remove label '{}' from a MSBT whose ATR1 section has a string table",
+                        label
+                    ));
+                }
+
+                Some((count, entry_size))
+            },
+            None => None,
+        };
+
+        for (_, other) in labels.iter_mut() {
+            if *other > index {
+                *other -= 1;
+            }
+        }
+
+        self.write_labels(slot_count, &labels);
+
+        let index = index as usize;
+
+        if let Some(mut strings) = messages {
+            if index < strings.len() {
+                strings.remove(index);
+            }
+
+            let mut data = (strings.len() as u32).to_le_bytes().to_vec();
+            let mut offset = 4 + strings.len() * 4;
+
+            for string in strings.iter() {
+                data.extend_from_slice(&(offset as u32).to_le_bytes());
+                offset += string.len();
+            }
+
+            for string in strings.iter() {
+                data.extend_from_slice(string);
+            }
+
+            if let Some(section) = self.section_mut(TXT2_MAGIC) {
+                section.data = data;
+            }
+        }
+
+        if let Some((count, entry_size)) = attributes.filter(|(count, _)| index < *count) {
+            if let Some(section) = self.section_mut(ATR1_MAGIC) {
+                let start = 8 + index * entry_size;
+                section.data.drain(start..start + entry_size);
+                section.data[0..4].copy_from_slice(&((count - 1) as u32).to_le_bytes());
+            }
+        }
+
+        if let Some(section) = self.section_mut(TSY1_MAGIC) {
+            let start = index * 4;
+
+            if start + 4 <= section.data.len() {
+                section.data.drain(start..start + 4);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().chain(std::iter::once(0)).flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn section(magic: &[u8], data: Vec<u8>) -> Section {
+        Section {
+            magic: magic.try_into().unwrap(),
+            data,
+        }
+    }
+
+    // Builds a MSBT with one message per label, where each message has the attribute [index; 4] and the style index * 10
+    fn build(labels: &[&str], atr_strings: bool) -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE];
+        header[0x0..0x8].copy_from_slice(MSBT_MAGIC);
+        header[0x8..0xA].copy_from_slice(LITTLE_ENDIAN_BOM);
+        header[0xE..0x10].copy_from_slice(&4u16.to_le_bytes());
+
+        let mut attributes = (labels.len() as u32).to_le_bytes().to_vec();
+        attributes.extend_from_slice(&4u32.to_le_bytes());
+        let mut styles = Vec::new();
+        let mut messages = (labels.len() as u32).to_le_bytes().to_vec();
+        let mut strings = Vec::new();
+
+        for (index, label) in labels.iter().enumerate() {
+            attributes.extend_from_slice(&[index as u8; 4]);
+            styles.extend_from_slice(&(index as u32 * 10).to_le_bytes());
+            messages.extend_from_slice(&((4 + labels.len() * 4 + strings.len()) as u32).to_le_bytes());
+            strings.extend(utf16(label));
+        }
+
+        if atr_strings {
+            attributes.extend_from_slice(b"str\0");
+        }
+
+        messages.extend(strings);
+
+        let mut raw = RawMsbt {
+            header,
+            sections: vec![
+                section(LBL1_MAGIC, Vec::new()),
+                section(ATR1_MAGIC, attributes),
+                section(TSY1_MAGIC, styles),
+                section(TXT2_MAGIC, messages),
+            ],
+        };
+
+        let labels: Vec<(String, u32)> = labels
+            .iter()
+            .enumerate()
+            .map(|(index, label)| (label.to_string(), index as u32))
+            .collect();
+        raw.write_labels(7, &labels);
+        raw.write()
+    }
+
+    fn messages(raw: &RawMsbt) -> Vec<Vec<u8>> {
+        read_messages(&raw.section(TXT2_MAGIC).unwrap().data)
+            .unwrap()
+            .into_iter()
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    fn attributes(raw: &RawMsbt) -> Vec<u8> {
+        raw.section(ATR1_MAGIC).unwrap().data[8..].to_vec()
+    }
+
+    fn styles(raw: &RawMsbt) -> Vec<u32> {
+        raw.section(TSY1_MAGIC)
+            .unwrap()
+            .data
+            .chunks(4)
+            .map(|style| u32::from_le_bytes(style.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let data = build(&["msg_a", "msg_b", "msg_c"], false);
+        let raw = RawMsbt::parse(&data).unwrap();
+
+        assert_eq!(raw.index_of("msg_b").unwrap(), Some(1));
+        assert_eq!(raw.index_of("msg_missing").unwrap(), None);
+        assert_eq!(messages(&raw)[2], utf16("msg_c"));
+        assert_eq!(read_u32(&data, 0x12).unwrap() as usize, data.len());
+        assert_eq!(raw.write(), data);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_little_endian_msbts() {
+        let mut data = build(&["msg_a"], false);
+        data[0x8..0xA].copy_from_slice(&[0xFE, 0xFF]);
+        assert!(RawMsbt::parse(&data).is_err());
+
+        data[0] = b'X';
+        assert!(RawMsbt::parse(&data).is_err());
+    }
+
+    #[test]
+    fn removes_a_message_with_its_attribute_and_style() {
+        let mut raw = RawMsbt::parse(&build(&["msg_a", "msg_b", "msg_c"], false)).unwrap();
+
+        assert!(raw.remove("msg_b").unwrap());
+        assert!(!raw.remove("msg_missing").unwrap());
+
+        let raw = RawMsbt::parse(&raw.write()).unwrap();
+
+        assert_eq!(raw.index_of("msg_b").unwrap(), None);
+        assert_eq!(raw.index_of("msg_c").unwrap(), Some(1));
+        assert_eq!(messages(&raw), [utf16("msg_a"), utf16("msg_c")]);
+        assert_eq!(attributes(&raw), [0, 0, 0, 0, 2, 2, 2, 2]);
+        assert_eq!(styles(&raw), [0, 20]);
+    }
+
+    #[test]
+    fn leaves_the_file_alone_when_removing_from_an_attribute_string_table() {
+        let data = build(&["msg_a", "msg_b"], true);
+        let mut raw = RawMsbt::parse(&data).unwrap();
+
+        assert!(raw.remove("msg_a").is_err());
+        assert_eq!(raw.write(), data);
+    }
+
+    #[test]
+    fn sets_and_adds_attributes() {
+        let mut raw = RawMsbt::parse(&build(&["msg_a", "msg_b"], false)).unwrap();
+
+        raw.set_attribute(1, &[7; 4]).unwrap();
+        raw.set_attribute(3, &[9; 4]).unwrap();
+        assert!(raw.set_attribute(0, &[1; 2]).is_err());
+
+        assert_eq!(read_u32(&raw.section(ATR1_MAGIC).unwrap().data, 0).unwrap(), 4);
+        assert_eq!(attributes(&raw), [0, 0, 0, 0, 7, 7, 7, 7, 0, 0, 0, 0, 9, 9, 9, 9]);
+    }
+
+    #[test]
+    fn cannot_add_attributes_before_a_string_table() {
+        let mut raw = RawMsbt::parse(&build(&["msg_a"], true)).unwrap();
+
+        assert!(raw.set_attribute(0, &[7; 4]).is_ok());
+        assert!(raw.set_attribute(1, &[7; 4]).is_err());
+    }
+
+    #[test]
+    fn sets_and_adds_styles() {
+        let mut raw = RawMsbt::parse(&build(&["msg_a", "msg_b"], false)).unwrap();
+
+        raw.set_style(0, 5).unwrap();
+        raw.set_style(3, 6).unwrap();
+
+        assert_eq!(styles(&raw), [5, 10, 0, 6]);
+    }
+}
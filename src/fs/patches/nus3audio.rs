@@ -0,0 +1,369 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use nus3audio::{AudioFile, Nus3audioFile};
+use serde::Deserialize;
+
+use super::{PatchFile, PatchHandler};
+
+/// The tracks a mod removes from a NUS3AUDIO, read from a `<name>.patch3audio.toml` file next to where the patch3audio would be:
+/// ```toml
+/// remove = ["se_mario_jump01", "se_mario_jump02"]
+/// ```
+#[derive(Debug, Deserialize)]
+struct RemovalList {
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+fn is_removal_list(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "toml")
+}
+
+/// Merges patch3audio files and removal lists onto a base NUS3AUDIO.
+///
+/// Tracks are matched by name:
+/// * A track with the same name as an existing one replaces its audio data, even when the new data is empty.
+/// * A track with a new name is added to the file.
+/// * A track listed in a removal list has its audio data blanked. The game finds tracks by their position in the file,
+///   so a removed track keeps its name and its ID, and a later patch can give it audio data again.
+///
+/// The ID of every track in a patch is treated as the ID the mod wants that track to have. It is honored when the ID is free,
+/// or when it currently belongs to a track that another mod positioned, in which case the later mod wins and the other track is moved
+/// to the end of the file. IDs belonging to tracks of the base file are never taken over, so that patch files built from a full copy
+/// keep working like they used to.
+///
+/// The IDs left unused by tracks moved to the ID they asked for are filled with empty tracks named `arcropolis_placeholder_<id>`,
+/// keeping every other track at the position matching its ID.
+pub struct Nus3audioMerger {
+    base: Nus3audioFile,
+    tracks: HashMap<String, AudioFile>,
+    // Which track uses which ID
+    ids: HashMap<u32, String>,
+    // The mod root that decided the ID of a track, if it isn't the one from the base file
+    owners: HashMap<String, PathBuf>,
+}
+
+impl Nus3audioMerger {
+    pub fn new(mut base: Nus3audioFile) -> Self {
+        let files = std::mem::take(&mut base.files);
+
+        let ids = files.iter().map(|file| (file.id, file.name.clone())).collect();
+        let tracks = files.into_iter().map(|file| (file.name.clone(), file)).collect();
+
+        Self {
+            base,
+            tracks,
+            ids,
+            owners: HashMap::new(),
+        }
+    }
+
+    fn next_free_id(&self) -> u32 {
+        self.ids.keys().max().map_or(0, |id| id + 1)
+    }
+
+    /// Tries to give `name` the requested ID on behalf of `root`. Returns false if the ID could not be given.
+    fn pin_id(&mut self, name: &str, requested: u32, root: &Path) -> bool {
+        let holder = match self.ids.get(&requested) {
+            Some(holder) if holder == name => return true,
+            Some(holder) => holder.clone(),
+            None => {
+                self.ids.insert(requested, name.to_string());
+                self.owners.insert(name.to_string(), root.to_path_buf());
+                return true;
+            },
+        };
+
+        match self.owners.get(&holder) {
+            Some(owner) if owner.as_path() != root => {
+                let new_id = self.next_free_id();
+
+                warn!(
+                    "patch3audio ID {} is requested by both '{}' (track '{}') and '{}' (track '{}'). '{}' wins, '{}' is moved to ID {}.",
+                    requested,
+                    owner.display(),
+                    holder,
+                    root.display(),
+                    name,
+                    root.display(),
+                    holder,
+                    new_id
+                );
+
+                if let Some(track) = self.tracks.get_mut(&holder) {
+                    track.id = new_id;
+                }
+
+                self.ids.insert(new_id, holder);
+                self.ids.insert(requested, name.to_string());
+                self.owners.insert(name.to_string(), root.to_path_buf());
+                true
+            },
+            // The mod is fighting with itself
+            Some(_) => false,
+            None => {
+                warn!(
+                    "patch3audio ID {} is requested by '{}' for track '{}', but it belongs to track '{}' of the base file. '{}' is given another ID.",
+                    requested,
+                    root.display(),
+                    name,
+                    holder,
+                    name
+                );
+                false
+            },
+        }
+    }
+
+    /// Blanks the audio data of every track of a removal list
+    fn remove_tracks(&mut self, patch: &PatchFile) -> Result<(), String> {
+        let text = std::fs::read_to_string(&patch.path).map_err(|e| format!("{:?}", e))?;
+        let list: RemovalList = toml::from_str(&text).map_err(|e| e.to_string())?;
+
+        for name in list.remove.iter() {
+            match self.tracks.get_mut(name) {
+                Some(track) => {
+                    debug!(
+                        "Removing track '{}' (ID {}) as requested by '{}'.",
+                        track.name,
+                        track.id,
+                        patch.root.display()
+                    );
+                    track.data.clear();
+                },
+                None => warn!(
+                    "patch3audio removal list `{}` removes track '{}', which does not exist.",
+                    patch.path.display(),
+                    name
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn add_patch(&mut self, patch: &PatchFile) -> Result<(), String> {
+        if is_removal_list(&patch.path) {
+            return self.remove_tracks(patch);
+        }
+
+        let data = std::fs::read(&patch.path).map_err(|e| format!("{:?}", e))?;
+        let modified_file = Nus3audioFile::from_bytes(&data);
+
+        for mut audio_file in modified_file.files {
+            let requested = audio_file.id;
+
+            if let Some(current_id) = self.tracks.get(&audio_file.name).map(|track| track.id) {
+                debug!("Found {}! Patching...", &audio_file.name);
+
+                if requested != current_id && self.pin_id(&audio_file.name, requested, &patch.root) {
+                    self.ids.remove(&current_id);
+                    audio_file.id = requested;
+                } else {
+                    audio_file.id = current_id;
+                }
+            } else {
+                debug!("Not found {}! Adding...", &audio_file.name);
+
+                if !self.pin_id(&audio_file.name, requested, &patch.root) {
+                    audio_file.id = self.next_free_id();
+                    self.ids.insert(audio_file.id, audio_file.name.clone());
+                    self.owners.insert(audio_file.name.clone(), patch.root.clone());
+                }
+            }
+
+            self.tracks.insert(audio_file.name.clone(), audio_file);
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Nus3audioFile {
+        let mut files: Vec<AudioFile> = self.tracks.into_values().collect();
+
+        // Fill the IDs nothing uses anymore, because the game finds an AudioFile by its position rather than its ID
+        let used: HashSet<u32> = files.iter().map(|file| file.id).collect();
+        let count = files.iter().map(|file| file.id + 1).max().unwrap_or(0);
+
+        for id in (0..count).filter(|id| !used.contains(id)) {
+            debug!("patch3audio: ID {} is unused, filling it with an empty track.", id);
+
+            files.push(AudioFile {
+                id,
+                name: format!("arcropolis_placeholder_{}", id),
+                data: Vec::new(),
+            });
+        }
+
+        // Sort the tracks by ID, because if we don't, the game loads the wrong AudioFiles on request.
+        files.sort_by(|a, b| a.id.cmp(&b.id));
+
+        self.base.files = files;
+        self.base
+    }
+}
+
+/// Merges patch3audio files and removal lists onto NUS3AUDIO files
+pub struct Nus3audioHandler;
+
+impl PatchHandler for Nus3audioHandler {
+    fn name(&self) -> &str {
+        "nus3audio"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["patch3audio", "patch3audio.toml"]
+    }
+
+    fn target_path(&self, local: &Path) -> PathBuf {
+        // Removal lists are named after the patch3audio, with an extra extension
+        if is_removal_list(local) {
+            local.with_extension("").with_extension("nus3audio")
+        } else {
+            local.with_extension("nus3audio")
+        }
+    }
+
+    fn patch(&self, _local: &Path, base: Vec<u8>, patches: &[PatchFile]) -> Result<Vec<u8>, String> {
+        // Parse the pre patch file into the nus3audio type and merge every patch onto it
+        let mut merger = Nus3audioMerger::new(Nus3audioFile::from_bytes(&base[..]));
+
+        for patch in patches.iter() {
+            if let Err(e) = merger.add_patch(patch) {
+                warn!("patch3audio file `{}` could not be read, skipping. Reason: {}", patch.path.display(), e);
+            }
+        }
+
+        let mut contents: Vec<u8> = Vec::new();
+        merger.finish().write(&mut contents);
+        Ok(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: u32, name: &str, data: &[u8]) -> AudioFile {
+        AudioFile {
+            id,
+            name: name.to_string(),
+            data: data.to_vec(),
+        }
+    }
+
+    fn nus3audio(tracks: Vec<AudioFile>) -> Nus3audioFile {
+        let mut file = Nus3audioFile::new();
+        file.files = tracks;
+        file
+    }
+
+    fn base() -> Nus3audioFile {
+        nus3audio(vec![track(0, "se_a", &[1; 4]), track(1, "se_b", &[2; 4]), track(2, "se_c", &[3; 4])])
+    }
+
+    fn write_patch(name: &str, root: &str, tracks: Vec<AudioFile>) -> PatchFile {
+        let path = std::env::temp_dir().join(format!("arcropolis_test_{}.patch3audio", name));
+        let mut contents = Vec::new();
+        nus3audio(tracks).write(&mut contents);
+        std::fs::write(&path, contents).unwrap();
+        PatchFile::new(Path::new(root), &path)
+    }
+
+    fn write_removal_list(name: &str, root: &str, contents: &str) -> PatchFile {
+        let path = std::env::temp_dir().join(format!("arcropolis_test_{}.patch3audio.toml", name));
+        std::fs::write(&path, contents).unwrap();
+        PatchFile::new(Path::new(root), &path)
+    }
+
+    fn merge(patches: &[PatchFile]) -> Vec<(u32, String, Vec<u8>)> {
+        let mut merger = Nus3audioMerger::new(base());
+
+        for patch in patches.iter() {
+            merger.add_patch(patch).unwrap();
+        }
+
+        merger.finish().files.into_iter().map(|file| (file.id, file.name, file.data)).collect()
+    }
+
+    fn names(files: &[(u32, String, Vec<u8>)]) -> Vec<&str> {
+        files.iter().map(|(_, name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn replaces_tracks_by_name_and_appends_new_ones() {
+        let patch = write_patch(
+            "replace",
+            "sd:/ultimate/mods/a",
+            vec![track(1, "se_b", &[9; 2]), track(3, "se_d", &[4; 4])],
+        );
+        let files = merge(&[patch]);
+
+        assert_eq!(names(&files), ["se_a", "se_b", "se_c", "se_d"]);
+        assert_eq!(files[1], (1, "se_b".to_string(), vec![9; 2]));
+        assert_eq!(files[3].0, 3);
+    }
+
+    #[test]
+    fn fills_the_ids_skipped_by_a_pinned_track_with_placeholders() {
+        let patch = write_patch("pinned", "sd:/ultimate/mods/a", vec![track(5, "se_new", &[4; 4])]);
+        let files = merge(&[patch]);
+
+        assert_eq!(
+            names(&files),
+            ["se_a", "se_b", "se_c", "arcropolis_placeholder_3", "arcropolis_placeholder_4", "se_new"]
+        );
+        assert!(files[3].2.is_empty());
+        assert_eq!(files.iter().map(|(id, ..)| *id).collect::<Vec<_>>(), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn never_takes_over_the_ids_of_the_base_file() {
+        let patch = write_patch("base_id", "sd:/ultimate/mods/a", vec![track(0, "se_new", &[4; 4])]);
+        let files = merge(&[patch]);
+
+        assert_eq!(files[0].1, "se_a");
+        assert_eq!(files[3], (3, "se_new".to_string(), vec![4; 4]));
+    }
+
+    #[test]
+    fn the_later_mod_wins_a_pinned_id() {
+        let first = write_patch("conflict_first", "sd:/ultimate/mods/a", vec![track(3, "se_first", &[4; 4])]);
+        let second = write_patch("conflict_second", "sd:/ultimate/mods/b", vec![track(3, "se_second", &[5; 4])]);
+        let files = merge(&[first, second]);
+
+        assert_eq!(names(&files), ["se_a", "se_b", "se_c", "se_second", "se_first"]);
+        assert_eq!(files[4].0, 4);
+    }
+
+    #[test]
+    fn removed_tracks_keep_their_slot() {
+        let removal = write_removal_list("remove", "sd:/ultimate/mods/a", "remove = [\"se_b\", \"se_missing\"]\n");
+        let files = merge(&[removal]);
+
+        assert_eq!(names(&files), ["se_a", "se_b", "se_c"]);
+        assert_eq!(files[1].0, 1);
+        assert!(files[1].2.is_empty());
+        assert_eq!(files[2].2, vec![3; 4]);
+    }
+
+    #[test]
+    fn empty_tracks_replace_the_data_instead_of_removing() {
+        let patch = write_patch("empty", "sd:/ultimate/mods/a", vec![track(1, "se_b", &[])]);
+        let files = merge(&[patch]);
+
+        assert_eq!(names(&files), ["se_a", "se_b", "se_c"]);
+        assert!(files[1].2.is_empty());
+    }
+
+    #[test]
+    fn removal_lists_target_the_nus3audio() {
+        let target = Nus3audioHandler.target_path(Path::new("sound/bank/se_mario.patch3audio.toml"));
+
+        assert_eq!(target, Path::new("sound/bank/se_mario.nus3audio"));
+        assert_eq!(Nus3audioHandler.target_path(Path::new("sound/bank/se_mario.patch3audio")), target);
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use super::{PatchFile, PatchHandler};
+
+// NUS3BANK layout, as far as it matters for patching:
+// 0x00: "NUS3" magic, followed by the size of the rest of the file
+// 0x08: "BANKTOC " magic, followed by the size of the table of contents
+// 0x14: number of sections, followed by a (magic, size) pair for each of them
+// The sections are laid out after the table of contents in the same order, each one starting with its own (magic, size) header
+static NUS3_MAGIC: &[u8] = b"NUS3";
+static BANKTOC_MAGIC: &[u8] = b"BANKTOC ";
+static TONE_MAGIC: &[u8] = b"TONE";
+
+// Every TONE entry starts with a 0xC bytes header, followed by the length of the name (including the null terminator) and the name itself.
+// The name is padded to 4 bytes, then followed by 0xC bytes of unknown data and the offset and size of the stream in the PACK section.
+const TONE_HEADER_SIZE: usize = 0xC;
+const TONE_NAME_PADDING: usize = 4;
+const TONE_STREAM_INFO_OFFSET: usize = 0xC;
+// The volume is the first parameter after the stream offset and size
+const TONE_VOLUME_OFFSET: usize = TONE_STREAM_INFO_OFFSET + 0x8;
+// Nothing past the volume is known well enough to be edited, which is why patch3bank files can only change volumes.
+// A value outside of what the game uses means the entry is laid out differently, so it is left alone.
+const TONE_VOLUME_RANGE: std::ops::RangeInclusive<f32> = 0.0..=16.0;
+
+/// The new volume of a single TONE entry, found by name.
+///
+/// This is the format of the entries in a `.patch3bank` file:
+/// ```yaml
+/// - name: se_mario_special_n01
+///   volume: 0.8
+/// ```
+/// The volume is the only parameter of a tone that can be edited, so any other field makes the whole file invalid.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToneVolumePatch {
+    pub name: String,
+    pub volume: f32,
+}
+
+struct ToneEntry {
+    name: String,
+    // Absolute offset of the entry in the file
+    offset: usize,
+    volume_offset: usize,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Locates the start of the TONE section data (right after its header)
+fn find_tone_section(data: &[u8]) -> Result<usize, String> {
+    if data.get(0x0..0x4) != Some(NUS3_MAGIC) || data.get(0x8..0x10) != Some(BANKTOC_MAGIC) {
+        return Err("File is not a valid NUS3BANK".to_string());
+    }
+
+    let toc_size = read_u32(data, 0x10).ok_or("Unexpected end of file while reading the table of contents")? as usize;
+    let section_count = read_u32(data, 0x14).ok_or("Unexpected end of file while reading the table of contents")? as usize;
+
+    let mut section_offset = 0x14 + toc_size;
+
+    for idx in 0..section_count {
+        let toc_entry = 0x18 + idx * 0x8;
+        let magic = data
+            .get(toc_entry..toc_entry + 4)
+            .ok_or("Unexpected end of file while reading the table of contents")?;
+        let size = read_u32(data, toc_entry + 4).ok_or("Unexpected end of file while reading the table of contents")? as usize;
+
+        if magic == TONE_MAGIC {
+            return Ok(section_offset + 0x8);
+        }
+
+        section_offset += 0x8 + size;
+    }
+
+    Err("NUS3BANK does not have a TONE section".to_string())
+}
+
+fn read_tone_entries(data: &[u8]) -> Result<Vec<ToneEntry>, String> {
+    let tone_offset = find_tone_section(data)?;
+    let count = read_u32(data, tone_offset).ok_or("Unexpected end of file while reading the TONE section")? as usize;
+
+    let mut entries = Vec::with_capacity(count);
+
+    for idx in 0..count {
+        let info = tone_offset + 0x4 + idx * 0x8;
+        let (offset, size) = match (read_u32(data, info), read_u32(data, info + 4)) {
+            (Some(offset), Some(size)) => (tone_offset + offset as usize, size as usize),
+            _ => return Err("Unexpected end of file while reading the TONE section".to_string()),
+        };
+
+        // Entries this small do not describe a sound and do not have a name
+        if size <= TONE_HEADER_SIZE {
+            continue;
+        }
+
+        let name_len = match data.get(offset + TONE_HEADER_SIZE) {
+            Some(len) => *len as usize,
+            None => continue,
+        };
+
+        let name_start = offset + TONE_HEADER_SIZE + 1;
+
+        let name = match data.get(name_start..name_start + name_len).map(|name| std::str::from_utf8(name)) {
+            Some(Ok(name)) => name.trim_end_matches('\0').to_string(),
+            _ => {
+                warn!("TONE entry #{} of NUS3BANK has an invalid name, skipping.", idx);
+                continue;
+            },
+        };
+
+        let name_end = ((name_start + name_len - offset + (TONE_NAME_PADDING - 1)) & !(TONE_NAME_PADDING - 1)) + offset;
+        let volume_offset = name_end + TONE_VOLUME_OFFSET;
+
+        if volume_offset + 4 > offset + size {
+            continue;
+        }
+
+        let volume = match read_u32(data, volume_offset).map(f32::from_bits) {
+            Some(volume) if volume.is_finite() && TONE_VOLUME_RANGE.contains(&volume) => volume,
+            _ => {
+                warn!(
+                    "TONE entry '{}' of NUS3BANK does not have a volume where it is expected, it will not be patched.",
+                    name
+                );
+                continue;
+            },
+        };
+
+        trace!("TONE entry '{}' has a volume of {}", name, volume);
+
+        entries.push(ToneEntry { name, offset, volume_offset });
+    }
+
+    Ok(entries)
+}
+
+/// Applies the volumes of every patch3bank file onto the NUS3BANK data, in order.
+/// Only existing values are modified, so the size of the file never changes.
+pub fn apply_patches(data: &mut [u8], patches: &[PatchFile]) -> Result<(), String> {
+    let entries: HashMap<String, ToneEntry> = read_tone_entries(data)?.into_iter().map(|entry| (entry.name.clone(), entry)).collect();
+
+    // Keep track of which mod last edited each tone so conflicts can be reported
+    let mut edited_by: HashMap<&str, &PatchFile> = HashMap::new();
+
+    for patch in patches.iter() {
+        let contents = match std::fs::read_to_string(&patch.path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("patch3bank file `{}` could not be read, skipping. Reason: {:?}", patch.path.display(), e);
+                continue;
+            },
+        };
+
+        let tone_patches: Vec<ToneVolumePatch> = match serde_yaml::from_str(&contents) {
+            Ok(tone_patches) => tone_patches,
+            Err(e) => {
+                warn!("patch3bank file `{}` is malformed, skipping. Reason: {}", patch.path.display(), e);
+                continue;
+            },
+        };
+
+        for tone_patch in tone_patches.iter() {
+            let (name, entry) = match entries.get_key_value(&tone_patch.name) {
+                Some(entry) => entry,
+                None => {
+                    warn!(
+                        "patch3bank file `{}` edits tone '{}', which does not exist in the NUS3BANK.",
+                        patch.path.display(),
+                        tone_patch.name
+                    );
+                    continue;
+                },
+            };
+
+            let volume = tone_patch.volume;
+
+            if !volume.is_finite() || !TONE_VOLUME_RANGE.contains(&volume) {
+                warn!(
+                    "patch3bank file `{}` sets the volume of tone '{}' to {}, which is out of range. Skipping.",
+                    patch.path.display(),
+                    name,
+                    volume
+                );
+                continue;
+            }
+
+            data[entry.volume_offset..entry.volume_offset + 4].copy_from_slice(&volume.to_le_bytes());
+
+            if let Some(previous) = edited_by.insert(name.as_str(), patch) {
+                if previous.root != patch.root {
+                    warn!(
+                        "Tone '{}' (entry at {:#x}) is edited by both '{}' and '{}'. The changes from '{}' take priority.",
+                        name,
+                        entry.offset,
+                        previous.root.display(),
+                        patch.root.display(),
+                        patch.root.display()
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Edits the volume of the tones of NUS3BANK files with patch3bank files. No other tone parameter can be changed.
+///
+/// NUS3BANKs that a modded NUS3AUDIO depends on are also served through this handler without any patch file,
+/// so that they get unshared along with their NUS3AUDIO.
+pub struct Nus3bankHandler;
+
+impl PatchHandler for Nus3bankHandler {
+    fn name(&self) -> &str {
+        "nus3bank"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["patch3bank"]
+    }
+
+    fn target_path(&self, local: &Path) -> PathBuf {
+        local.with_extension("nus3bank")
+    }
+
+    fn patch(&self, _local: &Path, mut base: Vec<u8>, patches: &[PatchFile]) -> Result<Vec<u8>, String> {
+        apply_patches(&mut base, patches)?;
+        Ok(base)
+    }
+
+    fn serves_vanilla(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone_entry(name: &str, volume: f32) -> Vec<u8> {
+        let mut entry = vec![0u8; TONE_HEADER_SIZE];
+        entry.push(name.len() as u8 + 1);
+        entry.extend_from_slice(name.as_bytes());
+        entry.push(0);
+
+        while entry.len() % TONE_NAME_PADDING != 0 {
+            entry.push(0);
+        }
+
+        entry.extend_from_slice(&[0u8; TONE_VOLUME_OFFSET]);
+        entry.extend_from_slice(&volume.to_le_bytes());
+        entry.extend_from_slice(&[0u8; 0x10]);
+        entry
+    }
+
+    fn section(magic: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut section = magic.to_vec();
+        section.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        section.extend_from_slice(data);
+        section
+    }
+
+    // Builds a NUS3BANK with a PROP section in front of the TONE section, so that finding it requires skipping another section
+    fn build_bank(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut tone = (entries.len() as u32).to_le_bytes().to_vec();
+        let mut offset = 0x4 + entries.len() * 0x8;
+
+        for entry in entries.iter() {
+            tone.extend_from_slice(&(offset as u32).to_le_bytes());
+            tone.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+            offset += entry.len();
+        }
+
+        for entry in entries.iter() {
+            tone.extend_from_slice(entry);
+        }
+
+        let prop = vec![0xAAu8; 0x10];
+
+        let mut body = BANKTOC_MAGIC.to_vec();
+        body.extend_from_slice(&(0x4u32 + 2 * 0x8).to_le_bytes());
+        body.extend_from_slice(&2u32.to_le_bytes());
+        body.extend_from_slice(b"PROP");
+        body.extend_from_slice(&(prop.len() as u32).to_le_bytes());
+        body.extend_from_slice(TONE_MAGIC);
+        body.extend_from_slice(&(tone.len() as u32).to_le_bytes());
+        body.extend(section(b"PROP", &prop));
+        body.extend(section(TONE_MAGIC, &tone));
+
+        let mut data = NUS3_MAGIC.to_vec();
+        data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        data.extend(body);
+        data
+    }
+
+    fn volume_of(data: &[u8], name: &str) -> f32 {
+        let entry = read_tone_entries(data).unwrap().into_iter().find(|entry| entry.name == name).unwrap();
+        f32::from_bits(read_u32(data, entry.volume_offset).unwrap())
+    }
+
+    fn write_patch(name: &str, contents: &str) -> PatchFile {
+        let path = std::env::temp_dir().join(format!("arcropolis_test_{}.patch3bank", name));
+        std::fs::write(&path, contents).unwrap();
+        PatchFile::new(Path::new("sd:/ultimate/mods/test"), &path)
+    }
+
+    #[test]
+    fn reads_tone_names_and_volumes() {
+        let data = build_bank(&[tone_entry("se_a", 1.0), tone_entry("se_longer_name", 0.5)]);
+        let entries = read_tone_entries(&data).unwrap();
+
+        assert_eq!(
+            entries.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>(),
+            ["se_a", "se_longer_name"]
+        );
+        assert_eq!(volume_of(&data, "se_a"), 1.0);
+        assert_eq!(volume_of(&data, "se_longer_name"), 0.5);
+    }
+
+    #[test]
+    fn skips_entries_without_a_name_or_a_volume() {
+        let data = build_bank(&[vec![0u8; 0x8], tone_entry("se_bad", 1000.0), tone_entry("se_good", 2.0)]);
+        let entries = read_tone_entries(&data).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "se_good");
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_nus3banks() {
+        let mut data = build_bank(&[tone_entry("se_a", 1.0)]);
+        data[0] = b'X';
+
+        assert!(read_tone_entries(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_banks_without_a_tone_section() {
+        let mut data = build_bank(&[tone_entry("se_a", 1.0)]);
+        let tone_magic = data.windows(4).position(|window| window == TONE_MAGIC).unwrap();
+        data[tone_magic..tone_magic + 4].copy_from_slice(b"PACK");
+
+        assert!(find_tone_section(&data).is_err());
+    }
+
+    #[test]
+    fn applies_volumes_in_order_without_changing_the_size() {
+        let mut data = build_bank(&[tone_entry("se_a", 1.0), tone_entry("se_b", 1.0)]);
+        let size = data.len();
+
+        let first = write_patch("order_first", "- name: se_a\n  volume: 0.5\n- name: se_b\n  volume: 3.0\n");
+        let second = write_patch("order_second", "- name: se_a\n  volume: 0.25\n");
+
+        apply_patches(&mut data, &[first, second]).unwrap();
+
+        assert_eq!(data.len(), size);
+        assert_eq!(volume_of(&data, "se_a"), 0.25);
+        assert_eq!(volume_of(&data, "se_b"), 3.0);
+    }
+
+    #[test]
+    fn ignores_out_of_range_volumes_and_unknown_tones() {
+        let mut data = build_bank(&[tone_entry("se_a", 1.0)]);
+        let patch = write_patch("out_of_range", "- name: se_a\n  volume: 100.0\n- name: se_missing\n  volume: 0.5\n");
+
+        apply_patches(&mut data, &[patch]).unwrap();
+
+        assert_eq!(volume_of(&data, "se_a"), 1.0);
+    }
+
+    #[test]
+    fn rejects_fields_other_than_the_volume() {
+        assert!(serde_yaml::from_str::<Vec<ToneVolumePatch>>("- name: se_a\n  volume: 0.5\n").is_ok());
+        assert!(serde_yaml::from_str::<Vec<ToneVolumePatch>>("- name: se_a\n  volume: 0.5\n  pitch: 2.0\n").is_err());
+        assert!(serde_yaml::from_str::<Vec<ToneVolumePatch>>("- name: se_a\n").is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    fs::File,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use super::{PatchFile, PatchHandler};
+use crate::PathExtension;
+
+/// Applies prcx/prcxml patches (and their stdat/stprm counterparts) onto param files
+pub struct PrcHandler;
+
+impl PatchHandler for PrcHandler {
+    fn name(&self) -> &str {
+        "prc"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["prcx", "prcxml", "stdatx", "stdatxml", "stprmx", "stprmxml"]
+    }
+
+    fn target_path(&self, local: &Path) -> PathBuf {
+        // patch files have different extensions
+        if local.has_extension("stdatx") || local.has_extension("stdatxml") {
+            local.with_extension("stdat")
+        } else if local.has_extension("stprmx") || local.has_extension("stprmxml") {
+            local.with_extension("stprm")
+        } else {
+            local.with_extension("prc")
+        }
+    }
+
+    fn patch(&self, _local: &Path, base: Vec<u8>, patches: &[PatchFile]) -> Result<Vec<u8>, String> {
+        let mut param_data = prcx::read_stream(&mut Cursor::new(base)).map_err(|_| "Unable to parse param data!".to_string())?;
+
+        for patch_file in patches.iter() {
+            let patch = if let Ok(patch) = prcx::open(&patch_file.path) {
+                patch
+            } else {
+                let file = File::open(&patch_file.path).map_err(|e| format!("{:?}", e))?;
+                let mut reader = std::io::BufReader::new(file);
+
+                prcx::read_xml(&mut reader).map_err(|_| "Unable to parse param patch data!".to_string())?
+            };
+
+            prcx::apply_patch(&patch, &mut param_data).map_err(|_| "Unable to patch param data!".to_string())?;
+        }
+
+        let mut writer = Cursor::new(Vec::new());
+        prcx::write_stream(&mut writer, &param_data).map_err(|e| format!("{:?}", e))?;
+        Ok(writer.into_inner())
+    }
+}
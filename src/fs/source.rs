@@ -0,0 +1,92 @@
+use std::{fmt, path::PathBuf};
+
+use super::patches::{self, PatchFile};
+
+/// Where the data of a file in the filesystem comes from
+#[derive(Debug, Clone)]
+pub enum FileSource {
+    /// Replaced by the file of a mod root
+    Mod(PathBuf),
+    /// Built by a patch format out of patch files, on top of the file of a mod root or the vanilla data.
+    /// The patch files are in the order they are applied in.
+    Patch {
+        format: String,
+        base: Option<PathBuf>,
+        patches: Vec<PatchFile>,
+    },
+    /// Provided by a plugin callback. If the callback fails, the data comes from the fallback instead.
+    Callback {
+        kind: &'static str,
+        fallback: Option<Box<FileSource>>,
+    },
+    /// In-memory data added by a plugin
+    Virtual,
+    /// The vanilla data, for files that only need to be unshared
+    Vanilla,
+}
+
+impl FileSource {
+    /// Adds a patch file to this source, at the place it is applied in
+    pub fn with_patch(self, format: &str, patch: PatchFile) -> Self {
+        match self {
+            Self::Patch { format, base, mut patches } => {
+                patches::insert_by_priority(&mut patches, patch);
+                Self::Patch { format, base, patches }
+            },
+            Self::Mod(root) => Self::Patch {
+                format: format.to_string(),
+                base: Some(root),
+                patches: vec![patch],
+            },
+            _ => Self::Patch {
+                format: format.to_string(),
+                base: None,
+                patches: vec![patch],
+            },
+        }
+    }
+
+    /// Puts a plugin callback in front of the previous source of a file, if it had one
+    pub fn callback(kind: &'static str, fallback: Option<FileSource>) -> Self {
+        Self::Callback {
+            kind,
+            fallback: fallback.map(Box::new),
+        }
+    }
+
+    /// Removes the most recent plugin callback of this kind. Returns None if nothing is left.
+    pub fn without_callback(self, kind: &'static str) -> Option<Self> {
+        match self {
+            Self::Callback { kind: current, fallback } if current == kind => fallback.map(|fallback| *fallback),
+            Self::Callback { kind: current, fallback } => Some(Self::Callback {
+                kind: current,
+                fallback: fallback.and_then(|fallback| fallback.without_callback(kind)).map(Box::new),
+            }),
+            source => Some(source),
+        }
+    }
+}
+
+impl fmt::Display for FileSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mod(root) => write!(f, "mod {}", root.display()),
+            Self::Patch { format, base, patches } => {
+                match base {
+                    Some(root) => write!(f, "{} patch of mod {}", format, root.display())?,
+                    None => write!(f, "{} patch of data.arc", format)?,
+                }
+
+                for patch in patches.iter() {
+                    write!(f, " <- {}", patch.path.display())?;
+                }
+
+                Ok(())
+            },
+            Self::Callback { kind, fallback: Some(fallback) } => write!(f, "{} callback, falling back to {}", kind, fallback),
+            Self::Callback { kind, fallback: None } => write!(f, "{} callback", kind),
+            Self::Virtual => write!(f, "virtual file"),
+            Self::Vanilla => write!(f, "data.arc"),
+        }
+    }
+}
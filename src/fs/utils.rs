@@ -8,13 +8,14 @@ use arc_config::ToExternal;
 use orbits::{FileLoader, Tree};
 use smash_arc::Hash40;
 
-use super::{ApiCallback, ApiLoader};
+use super::{
+    cache::DiscoveredFile,
+    patches::{self, PatchHandler},
+    ApiCallback, ApiLoader, FileSource,
+};
 use crate::{hashes, PathExtension};
 
-pub fn make_hash_maps<L: FileLoader>(tree: &Tree<L>) -> (HashMap<Hash40, usize>, HashMap<Hash40, PathBuf>)
-where
-    <L as FileLoader>::ErrorType: Debug,
-{
+pub fn make_hash_maps(files: &[(PathBuf, DiscoveredFile)]) -> (HashMap<Hash40, usize>, HashMap<Hash40, PathBuf>, HashMap<Hash40, FileSource>) {
     // This defines the previously undefined behavior of what happens when you have two files that overlap each other due to
     // regional things
     // I.E.: ui/message/msg_menu.msbt and ui/message/msg_menu+us_en.msbt
@@ -27,35 +28,33 @@ where
     let mut regional_overrides = HashSet::new();
     let mut size_map = HashMap::new();
     let mut path_map = HashMap::new();
-    tree.walk_paths(|node, ty| {
-        if !ty.is_file() {
-            return;
+    let mut source_map = HashMap::new();
+    for (root, file) in files.iter() {
+        let local = &file.local;
+
+        // Patch files are never loaded by the game themselves
+        if patches::handler_for_file(local).is_some() {
+            continue;
         }
 
-        if let Some(size) = tree.query_filesize(node.get_local()) {
-            match node.get_local().smash_hash() {
-                Ok(hash) => {
-                    if regional_overrides.contains(&hash) {
-                        return;
-                    }
+        let hash = file.hash();
 
-                    let is_regional_variant = if let Some(node) = node.get_local().to_str() { node.contains('+') } else { false };
+        if regional_overrides.contains(&hash) {
+            continue;
+        }
 
-                    size_map.insert(hash, size);
-                    path_map.insert(hash, node.get_local().to_path_buf());
+        let is_regional_variant = if let Some(node) = local.to_str() { node.contains('+') } else { false };
 
-                    if is_regional_variant {
-                        regional_overrides.insert(hash);
-                    }
-                },
-                Err(e) => error!("Failed to get hash for {}. Reason: {:?}", node.get_local().display(), e),
-            }
-        } else {
-            error!("Failed to stat file {}. This file may have issues.", node.full_path().display());
+        size_map.insert(hash, file.size);
+        path_map.insert(hash, local.to_path_buf());
+        source_map.insert(hash, FileSource::Mod(root.to_path_buf()));
+
+        if is_regional_variant {
+            regional_overrides.insert(hash);
         }
-    });
+    }
 
-    (size_map, path_map)
+    (size_map, path_map, source_map)
 }
 
 pub fn get_required_nus3banks<L: FileLoader>(tree: &Tree<L>, unshare_blacklist: &[hash40::Hash40]) -> HashSet<PathBuf>
@@ -94,6 +93,16 @@ where
     nus3audio_deps
 }
 
+/// Finds the patch files that were not collected during discovery, as (root, local) pairs.
+/// Formats registered by chainloaded plugins are only known once discovery is over, so their patch files end up in the tree.
+pub fn get_uncollected_patch_files(files: &[(PathBuf, DiscoveredFile)]) -> Vec<(PathBuf, PathBuf)> {
+    files
+        .iter()
+        .filter(|(_, file)| patches::handler_for_file(&file.local).is_some())
+        .map(|(root, file)| (root.clone(), file.local.clone()))
+        .collect()
+}
+
 pub fn add_file_to_api_tree<P: AsRef<Path>, Q: AsRef<Path>>(
     tree: &mut Tree<ApiLoader>,
     root: P,
@@ -115,93 +124,15 @@ pub fn add_file_to_api_tree<P: AsRef<Path>, Q: AsRef<Path>>(
     }
 }
 
-/// Adds a PRC patch file and information to the API loader
-pub fn add_prc_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLoader>, phys_root: P, local: Q) -> Option<Hash40> {
-    let local = local.as_ref();
-    let base_local = if local.has_extension("prcx") || local.has_extension("prcxml") {
-        // patch files have different extensions
-        local.with_extension("prc")
-    } else if local.has_extension("stdatx") || local.has_extension("stdatxml") {
-        local.with_extension("stdat")
-    } else if local.has_extension("stprmx") || local.has_extension("stprmxml") {
-        local.with_extension("stprm")
-    } else {
-        unreachable!()
-    };
-    let base_local = if let Some(name) = base_local.file_name().and_then(|os_str| os_str.to_str()) {
-        if let Some(idx) = name.find('+') {
-            let mut new_name = name.to_string();
-            new_name.replace_range(idx..idx + 6, "");
-            base_local.with_file_name(new_name)
-        } else {
-            base_local
-        }
-    } else {
-        base_local
-    };
-    let full_path = phys_root.as_ref().join(local); // need the full path so that our API loader can load it
-    match base_local.smash_hash() {
-        Ok(hash) => {
-            tree.insert_file("api:/patch-prc", &base_local);
-            tree.loader.push_entry(hash, Path::new("api:/patch-prc"), ApiCallback::None);
-            // We need to add our file to the vector of patch files
-            tree.loader.insert_prc_patch(hash, &full_path);
-            if let Some(local) = local.to_str() {
-                hashes::add(local);
-            }
-            if let Some(base_local) = base_local.to_str() {
-                hashes::add(base_local);
-            }
-            Some(hash)
-        },
-        Err(e) => {
-            error!("Could not add file {} to API tree. Reason: {:?}", full_path.display(), e);
-            None
-        },
-    }
-}
-
-/// Adds a MSBT patch file and information to the API loader
-pub fn add_msbt_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLoader>, phys_root: P, local: Q) -> Option<Hash40> {
-    let local = local.as_ref();
-    let base_local = local.with_extension("msbt"); // patch files have different extensions
-    let base_local = if let Some(name) = base_local.file_name().and_then(|os_str| os_str.to_str()) {
-        if let Some(idx) = name.find('+') {
-            let mut new_name = name.to_string();
-            new_name.replace_range(idx..idx + 6, "");
-            base_local.with_file_name(new_name)
-        } else {
-            base_local
-        }
-    } else {
-        base_local
-    };
-    let full_path = phys_root.as_ref().join(local); // need the full path so that our API loader can load it
-    match base_local.smash_hash() {
-        Ok(hash) => {
-            tree.insert_file("api:/patch-msbt", &base_local);
-            tree.loader.push_entry(hash, Path::new("api:/patch-msbt"), ApiCallback::None);
-            // We need to add our file to the vector of patch files
-            tree.loader.insert_msbt_patch(hash, &full_path);
-            if let Some(local) = local.to_str() {
-                hashes::add(local);
-            }
-            if let Some(base_local) = base_local.to_str() {
-                hashes::add(base_local);
-            }
-            Some(hash)
-        },
-        Err(e) => {
-            error!("Could not add file {} to API tree. Reason: {:?}", full_path.display(), e);
-            None
-        },
-    }
-}
-
-pub fn add_nus3audio_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLoader>, phys_root: P, local: Q) -> Option<Hash40> {
+/// Adds a patch file of any format and information to the API loader
+pub fn add_patch_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    tree: &mut Tree<ApiLoader>,
+    handler: &dyn PatchHandler,
+    phys_root: P,
+    local: Q,
+) -> Option<Hash40> {
     let local = local.as_ref();
-    let base_local = local.with_extension("nus3audio");
-
+    let base_local = handler.target_path(local); // patch files have different extensions
     let base_local = if let Some(name) = base_local.file_name().and_then(|os_str| os_str.to_str()) {
         if let Some(idx) = name.find('+') {
             let mut new_name = name.to_string();
@@ -216,10 +147,11 @@ pub fn add_nus3audio_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLo
     let full_path = phys_root.as_ref().join(local); // need the full path so that our API loader can load it
     match base_local.smash_hash() {
         Ok(hash) => {
-            tree.insert_file("api:/patch-nus3audio", &base_local);
-            tree.loader.push_entry(hash, Path::new("api:/patch-nus3audio"), ApiCallback::None);
+            let api_root = handler.api_root();
+            tree.insert_file(&api_root, &base_local);
+            tree.loader.push_entry(hash, &api_root, ApiCallback::None);
             // We need to add our file to the vector of patch files
-            tree.loader.insert_nus3audio_patch(hash, &full_path);
+            tree.loader.insert_patch(hash, phys_root.as_ref(), &full_path);
             if let Some(local) = local.to_str() {
                 hashes::add(local);
             }
@@ -234,95 +166,3 @@ pub fn add_nus3audio_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLo
         },
     }
 }
-
-pub fn add_motionlist_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLoader>, phys_root: P, local: Q) -> Option<Hash40> {
-    let local = local.as_ref();
-    let base_local = local.with_extension("bin");
-
-    let base_local = if let Some(name) = base_local.file_name().and_then(|os_str| os_str.to_str()) {
-        if let Some(idx) = name.find('+') {
-            let mut new_name = name.to_string();
-            new_name.replace_range(idx..idx + 6, "");
-            base_local.with_file_name(new_name)
-        } else {
-            base_local
-        }
-    } else {
-        base_local
-    };
-    let full_path = phys_root.as_ref().join(local); // need the full path so that our API loader can load it
-    if let Some(name) = full_path.file_name() {
-        if name.to_str().unwrap().contains(&"motion_list") {
-            match base_local.smash_hash() {
-                Ok(hash) => {
-                    tree.insert_file("api:/patch-motionlist", &base_local);
-                    tree.loader.push_entry(hash, Path::new("api:/patch-motionlist"), ApiCallback::None);
-                    // We need to add our file to the vector of patch files
-                    tree.loader.insert_motionlist_patch(hash, &full_path);
-                    if let Some(local) = local.to_str() {
-                        hashes::add(local);
-                    }
-                    if let Some(base_local) = base_local.to_str() {
-                        hashes::add(base_local);
-                    }
-                    return Some(hash);
-                },
-                Err(e) => {
-                    error!("Could not add file {} to API tree. Reason: {:?}", full_path.display(), e);
-                    return None;
-                },
-            }
-        }
-    }
-    error!(
-        "Could not add file {} to API tree. Reason: This is not a motion_list.bin file.",
-        full_path.display()
-    );
-    None
-}
-
-pub fn add_bgm_property_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLoader>, phys_root: P, local: Q) -> Option<Hash40> {
-    let local = local.as_ref();
-    let base_local = local.with_extension("bin");
-
-    let base_local = if let Some(name) = base_local.file_name().and_then(|os_str| os_str.to_str()) {
-        if let Some(idx) = name.find('+') {
-            let mut new_name = name.to_string();
-            new_name.replace_range(idx..idx + 6, "");
-            base_local.with_file_name(new_name)
-        } else {
-            base_local
-        }
-    } else {
-        base_local
-    };
-    let full_path = phys_root.as_ref().join(local); // need the full path so that our API loader can load it
-    if let Some(name) = full_path.file_name() {
-        if name.to_str().unwrap().contains(&"bgm_property") {
-            match base_local.smash_hash() {
-                Ok(hash) => {
-                    tree.insert_file("api:/patch-bgm_property", &base_local);
-                    tree.loader.push_entry(hash, Path::new("api:/patch-bgm_property"), ApiCallback::None);
-                    // We need to add our file to the vector of patch files
-                    tree.loader.insert_bgm_property_patch(hash, &full_path);
-                    if let Some(local) = local.to_str() {
-                        hashes::add(local);
-                    }
-                    if let Some(base_local) = base_local.to_str() {
-                        hashes::add(base_local);
-                    }
-                    return Some(hash);
-                },
-                Err(e) => {
-                    error!("Could not add file {} to API tree. Reason: {:?}", full_path.display(), e);
-                    return None;
-                },
-            }
-        }
-    }
-    error!(
-        "Could not add file {} to API tree. Reason: This is not a bgm_property.bin file.",
-        full_path.display()
-    );
-    None
-}
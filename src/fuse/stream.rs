@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+use nn_fuse::{AccessorResult, DAccessor, FAccessor, FileAccessor, FileSystemAccessor, FsAccessor, FsEntryType};
+use smash_arc::Hash40;
+
+use crate::api::StreamDataCallbackFn;
+
+/// Serves the data of a stream file chunk by chunk, straight from the plugin that registered it
+pub struct StreamDataFileAccessor {
+    hash: Hash40,
+    size: usize,
+    callback: StreamDataCallbackFn,
+}
+
+impl FileAccessor for StreamDataFileAccessor {
+    fn read(&mut self, buffer: &mut [u8], offset: usize) -> Result<usize, AccessorResult> {
+        debug!(target: "no-mod-path", "StreamDataFileAccessor::read - Buffer length: {:#x}, Offset: {:#x}", buffer.len(), offset);
+
+        if offset >= self.size {
+            return Ok(0);
+        }
+
+        let len = buffer.len().min(self.size - offset);
+        let mut read = 0;
+
+        if (self.callback)(self.hash.0, buffer.as_mut_ptr(), len, offset, &mut read) {
+            Ok(read.min(len))
+        } else {
+            Err(AccessorResult::Unexpected)
+        }
+    }
+
+    fn get_size(&mut self) -> Result<usize, AccessorResult> {
+        Ok(self.size)
+    }
+}
+
+pub struct StreamDataFs;
+
+impl StreamDataFs {
+    fn find_callback(path: &Path) -> Option<(Hash40, StreamDataCallbackFn, usize)> {
+        let hash = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| u64::from_str_radix(name, 16).ok())
+            .map(Hash40)?;
+
+        let fs = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
+        fs.get()
+            .virt()
+            .loader
+            .get_stream_data_callback(hash)
+            .map(|(callback, size)| (hash, callback, size))
+    }
+}
+
+impl FileSystemAccessor for StreamDataFs {
+    fn get_entry_type(&self, path: &Path) -> Result<FsEntryType, AccessorResult> {
+        debug!(target: "no-mod-path", "StreamDataFs::get_entry_type - Path: {}", path.display());
+
+        if Self::find_callback(path).is_some() {
+            Ok(FsEntryType::File)
+        } else {
+            Err(AccessorResult::PathNotFound)
+        }
+    }
+
+    fn open_file(&self, path: &Path, mode: skyline::nn::fs::OpenMode) -> Result<*mut FAccessor, AccessorResult> {
+        debug!(target: "no-mod-path", "StreamDataFs::open_file - Path: {}", path.display());
+
+        // Only reading is supported
+        if mode & 1 == 0 || mode >> 1 & 1 != 0 || mode >> 2 & 1 != 0 {
+            return Err(AccessorResult::Unsupported);
+        }
+
+        match Self::find_callback(path) {
+            Some((hash, callback, size)) => Ok(FAccessor::new(StreamDataFileAccessor { hash, size, callback }, mode)),
+            None => Err(AccessorResult::PathNotFound),
+        }
+    }
+
+    fn open_directory(&self, _path: &Path, _mode: skyline::nn::fs::OpenDirectoryMode) -> Result<*mut DAccessor, AccessorResult> {
+        Err(AccessorResult::Unsupported)
+    }
+}
+
+/// The path the game is given to stream a file provided through a data callback
+pub fn virtual_path(hash: Hash40) -> PathBuf {
+    PathBuf::from(format!("apistream:/{:016x}", hash.0))
+}
+
+pub fn install_stream_data_fs() {
+    let accessor = FsAccessor::new(StreamDataFs);
+    unsafe {
+        nn_fuse::mount("apistream", &mut *accessor).unwrap();
+    }
+    info!("Finished mounting apistream:/");
+}
@@ -268,7 +268,12 @@ fn initial_loading(_ctx: &InlineCtx) {
     replacement::lookup::initialize(Some(arc));
     let mut filesystem = GLOBAL_FILESYSTEM.write();
     *filesystem = filesystem.take().finish(arc).unwrap();
-    filesystem.process_mods();
+    // The lock is released around the events, since synchronous callbacks might call into the API
+    drop(filesystem);
+    api::event::send_event_v2(api::event::QueuedEvent::new(api::event::EventKind::ProcessModsStarting));
+    GLOBAL_FILESYSTEM.write().process_mods();
+    api::event::send_event_v2(api::event::QueuedEvent::new(api::event::EventKind::ProcessModsFinished));
+    let mut filesystem = GLOBAL_FILESYSTEM.write();
     filesystem.share_hashes();
     filesystem.patch_files();
 
@@ -285,9 +290,17 @@ fn initial_loading(_ctx: &InlineCtx) {
                 let _ = writeln!(output, "{}", node.full_path().display());
             }
         });
+
+        // Where every file comes from is listed after the tree
+        let _ = writeln!(output);
+        let _ = writeln!(output, "File sources:");
+        if let Err(e) = filesystem.dump_file_sources(&mut output) {
+            error!("Failed to dump the file sources. Reason: {:?}", e);
+        }
     }
     drop(filesystem);
     fuse::mods::install_mod_fs();
+    fuse::stream::install_stream_data_fs();
     api::event::send_event(Event::ModFilesystemMounted);
 
     #[cfg(feature = "online")]
@@ -479,6 +492,8 @@ pub fn main() {
         println!("[arcropolis] Failed to initialize logger. Reason: {:?}", err);
     }
 
+    utils::paths::ensure_mod_folders_exist();
+
     // Acquire the filesystem and promise it to the initial_loading hook
     let mut filesystem = GLOBAL_FILESYSTEM.write();
 
@@ -489,7 +504,6 @@ pub fn main() {
                 let curr_thread = nn::os::GetCurrentThread();
                 nn::os::ChangeThreadPriority(curr_thread, 0);
             }
-            std::thread::sleep(std::time::Duration::from_millis(5000));
             fs::perform_discovery()
         })
         .unwrap();
@@ -1,7 +1,11 @@
 // #![feature(proc_macro_hygiene)]
 
-use std::{collections::HashSet, path::Path};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
 
+use camino::Utf8PathBuf;
 use serde::{Deserialize, Serialize};
 use skyline_web::Webpage;
 use smash_arc::Hash40;
@@ -16,14 +20,45 @@ pub struct Information {
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Entry {
-    id: Option<u32>,
-    folder_name: Option<String>,
-    is_disabled: Option<bool>,
-    display_name: Option<String>,
-    authors: Option<String>,
-    version: Option<String>,
-    description: Option<String>,
-    category: Option<String>,
+    pub id: Option<u32>,
+    pub folder_name: Option<String>,
+    /// The mod folder the mod is in
+    pub mod_folder: Option<String>,
+    /// The collection the mod is in, relative to its mod folder
+    pub collection: Option<String>,
+    pub is_disabled: Option<bool>,
+    pub display_name: Option<String>,
+    pub authors: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub category: Option<String>,
+    /// A stable identifier that other mods can refer to this one by, instead of its folder name
+    pub mod_id: Option<String>,
+    /// Mods that have to be enabled along with this one
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Mods that cannot be enabled along with this one
+    #[serde(default)]
+    pub conflicts_with: Vec<String>,
+    /// Mods whose files this one overrides when both provide the same file
+    #[serde(default)]
+    pub load_after: Vec<String>,
+    /// The rank of the mod in the priority of the workspace, 0 being the highest
+    #[serde(skip_deserializing)]
+    pub priority: Option<usize>,
+}
+
+impl Entry {
+    /// The full path of the mod folder, which is what presets and priorities are keyed off
+    pub fn path(&self) -> String {
+        let mod_folder = self.mod_folder.clone().unwrap_or_else(|| utils::paths::mods().to_string());
+        let folder_name = self.folder_name.as_deref().unwrap_or_default();
+
+        match &self.collection {
+            Some(collection) => format!("{}/{}/{}", mod_folder, collection, folder_name),
+            None => format!("{}/{}", mod_folder, folder_name),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -32,74 +67,93 @@ pub enum ArcadiaMessage {
     ChangeAll { state: bool },
     ChangeIndexes { state: bool, indexes: Vec<usize> },
     DebugPrint { message: String },
+    SetPriority { order: Vec<usize> },
     GetModSize,
     Closure,
 }
 
-pub fn get_mods(presets: &HashSet<Hash40>) -> Vec<Entry> {
-    let mut id: u32 = 0;
+/// Builds the entry of a mod out of its info.toml, filling in defaults for anything it does not provide.
+/// If the info.toml is not valid, the default entry is returned along with the error.
+/// `folders` are the mod folders, as given by `utils::paths::mod_folders`.
+pub fn read_entry(id: u32, mod_path: &Path, disabled: bool, folders: &[Utf8PathBuf]) -> Result<Entry, (Entry, toml::de::Error)> {
     let use_folder_name = config::use_folder_name();
-    std::fs::read_dir(utils::paths::mods())
-        .unwrap()
-        .enumerate()
-        .filter_map(|(_i, path)| {
-            let path_to_be_used = path.unwrap().path();
-
-            if path_to_be_used.is_file() {
-                return None;
-            }
 
-            let disabled = !presets.contains(&Hash40::from(path_to_be_used.to_str().unwrap()));
-
-            let folder_name = Path::new(&path_to_be_used).file_name().unwrap().to_os_string().into_string().unwrap();
+    let folder_name = mod_path.file_name().unwrap().to_os_string().into_string().unwrap();
+    let mod_folder = utils::paths::mod_folder_of(mod_path, folders).map(|folder| folder.to_string());
+    let collection = utils::paths::collection_of(mod_path, folders).map(|collection| collection.to_string_lossy().into_owned());
+
+    let info_path = format!("{}/info.toml", mod_path.display());
+
+    let default_entry = Entry {
+        id: Some(id),
+        folder_name: Some(folder_name.clone()),
+        mod_folder: mod_folder.clone(),
+        collection: collection.clone(),
+        is_disabled: Some(disabled),
+        version: Some("???".to_string()),
+        // description: Some("".to_string()),
+        category: Some("Misc".to_string()),
+        ..Default::default()
+    };
 
-            let info_path = format!("{}/info.toml", path_to_be_used.display());
+    match toml::from_str::<Entry>(&std::fs::read_to_string(info_path).unwrap_or_default()) {
+        Ok(res) => Ok(Entry {
+            id: Some(id),
+            folder_name: Some(folder_name.clone()),
+            mod_folder,
+            collection,
+            display_name: if use_folder_name { Some(folder_name) } else { res.display_name.or(Some(folder_name)) },
+            authors: res.authors.or_else(|| Some(String::from("???"))),
+            is_disabled: Some(disabled),
+            version: res.version.or_else(|| Some(String::from("???"))),
+            category: res.category.map_or(Some(String::from("Misc")), |cat| {
+                if cat == "Music" {
+                    Some("Audio".to_string())
+                } else {
+                    Some(cat)
+                }
+            }),
+            description: Some(res.description.unwrap_or_default().replace('\n', "<br />")),
+            mod_id: res.mod_id,
+            requires: res.requires,
+            conflicts_with: res.conflicts_with,
+            load_after: res.load_after,
+            priority: None,
+        }),
+        Err(e) => Err((default_entry, e)),
+    }
+}
 
-            let default_entry = Entry {
-                id: Some(id),
-                folder_name: Some(folder_name.clone()),
-                is_disabled: Some(disabled),
-                version: Some("???".to_string()),
-                // description: Some("".to_string()),
-                category: Some("Misc".to_string()),
-                ..Default::default()
-            };
+pub fn get_mods(presets: &HashSet<Hash40>) -> Vec<Entry> {
+    let folders = utils::paths::mod_folders();
+    let mut id: u32 = 0;
+    utils::paths::mod_roots()
+        .into_iter()
+        .map(|path_to_be_used| {
+            let disabled = !presets.contains(&Hash40::from(path_to_be_used.to_str().unwrap()));
 
-            let mod_info = match toml::from_str::<Entry>(&std::fs::read_to_string(info_path).unwrap_or_default()) {
-                Ok(res) => Entry {
-                    id: Some(id),
-                    folder_name: Some(folder_name.clone()),
-                    display_name: if use_folder_name { Some(folder_name) } else { res.display_name.or(Some(folder_name)) },
-                    authors: res.authors.or_else(|| Some(String::from("???"))),
-                    is_disabled: Some(disabled),
-                    version: res.version.or_else(|| Some(String::from("???"))),
-                    category: res.category.map_or(Some(String::from("Misc")), |cat| {
-                        if cat == "Music" {
-                            Some("Audio".to_string())
-                        } else {
-                            Some(cat)
-                        }
-                    }),
-                    description: Some(res.description.unwrap_or_default().replace('\n', "<br />")),
-                },
-                Err(e) => {
-                    skyline_web::DialogOk::ok(format!("The following info.toml is not valid: \n\n* '{}'\n\nError: {}", folder_name, e,));
+            let mod_info = match read_entry(id, &path_to_be_used, disabled, &folders) {
+                Ok(entry) => entry,
+                Err((default_entry, e)) => {
+                    skyline_web::DialogOk::ok(format!(
+                        "The following info.toml is not valid: \n\n* '{}'\n\nError: {}",
+                        default_entry.folder_name.as_deref().unwrap_or_default(),
+                        e,
+                    ));
                     default_entry
                 },
             };
 
             id += 1;
 
-            Some(mod_info)
+            mod_info
         })
         .collect()
 }
 
 pub fn show_arcadia(workspace: Option<String>) {
-    let umm_path = utils::paths::mods();
-
-    if !umm_path.exists() {
-        skyline_web::DialogOk::ok("It seems the directory specified in your configuration does not exist.");
+    if !utils::paths::mod_folders().iter().any(|folder| folder.exists()) {
+        skyline_web::DialogOk::ok("It seems the directories specified in your configuration do not exist.");
         return;
     }
     let workspace_name: String =
@@ -108,15 +162,25 @@ pub fn show_arcadia(workspace: Option<String>) {
     let presets = config::presets::get_preset(&workspace_name).unwrap();
     let mut new_presets = presets.clone();
 
-    let mods: Information = Information {
+    let priority = config::priorities::get_priority(&workspace_name).unwrap_or_default();
+    let mut new_priority = priority.clone();
+
+    let mut mods: Information = Information {
         entries: get_mods(&presets),
         workspace: workspace_name.clone(),
     };
 
+    let mut ranked: Vec<usize> = (0..mods.entries.len()).collect();
+    config::priorities::sort_by_priority(&mut ranked, &priority, |&id| PathBuf::from(mods.entries[id].path()));
+
+    for (rank, id) in ranked.into_iter().enumerate() {
+        mods.entries[id].priority = Some(rank);
+    }
+
     // region Setup Preview Images
     let mut images: Vec<(String, Vec<u8>)> = Vec::new();
     for item in &mods.entries {
-        let path = &umm_path.join(item.folder_name.as_ref().unwrap()).join("preview.webp");
+        let path = Path::new(&item.path()).join("preview.webp");
 
         if path.exists() {
             images.push((format!("img/{}", item.id.unwrap()), std::fs::read(path).unwrap()));
@@ -154,7 +218,7 @@ pub fn show_arcadia(workspace: Option<String>) {
     while let Ok(message) = session.recv_json::<ArcadiaMessage>() {
         match message {
             ArcadiaMessage::ToggleMod { id, state } => {
-                let path = format!("{}/{}", umm_path, mods.entries[id].folder_name.as_ref().unwrap());
+                let path = mods.entries[id].path();
                 let hash = Hash40::from(path.as_str());
                 debug!("Setting {} to {}", path, state);
 
@@ -173,7 +237,7 @@ pub fn show_arcadia(workspace: Option<String>) {
                     new_presets.clear();
                 } else {
                     for item in mods.entries.iter() {
-                        let path = format!("{}/{}", umm_path, item.folder_name.as_ref().unwrap());
+                        let path = item.path();
                         let hash = Hash40::from(path.as_str());
 
                         new_presets.insert(hash);
@@ -182,7 +246,7 @@ pub fn show_arcadia(workspace: Option<String>) {
             },
             ArcadiaMessage::ChangeIndexes { state, indexes } => {
                 for idx in indexes {
-                    let path = format!("{}/{}", umm_path, mods.entries[idx].folder_name.as_ref().unwrap());
+                    let path = mods.entries[idx].path();
                     let hash = Hash40::from(path.as_str());
                     debug!("Setting {} to {}", path, state);
 
@@ -193,6 +257,15 @@ pub fn show_arcadia(workspace: Option<String>) {
                     }
                 }
             },
+            ArcadiaMessage::SetPriority { order } => {
+                new_priority = order
+                    .iter()
+                    .filter_map(|id| mods.entries.get(*id))
+                    .map(|item| Hash40::from(item.path().as_str()))
+                    .collect();
+
+                debug!("Mod priority has been updated");
+            },
             ArcadiaMessage::DebugPrint { message } => {
                 println!("session says: {}", message);
             },
@@ -211,7 +284,15 @@ pub fn show_arcadia(workspace: Option<String>) {
     let active_workspace = config::workspaces::get_active_workspace_name().unwrap();
     config::presets::replace_preset(&workspace_name, &new_presets).unwrap();
 
-    if new_presets != presets {
+    if new_priority != priority {
+        if let Err(e) = config::priorities::replace_priority(&workspace_name, &new_priority) {
+            error!("Failed to save the mod priority of workspace '{}'. Reason: {}", workspace_name, e);
+        }
+    }
+
+    if new_presets != presets || new_priority != priority {
+        crate::api::event::send_event_v2(crate::api::event::QueuedEvent::workspace_changed(&workspace_name));
+
         // Acquire the filesystem so we can check if it's already finished or not (for boot-time mod manager)
         if let Some(_filesystem) = crate::GLOBAL_FILESYSTEM.try_read() {
             if active_workspace.eq(&workspace_name) && skyline_web::Dialog::yes_no("Your preset has successfully been updated!<br>Your changes will take effect on the next boot.<br>Would you like to reboot the game to reload your mods?") {
@@ -2,7 +2,9 @@
 
 use serde::Deserialize;
 use skyline_config::{ConfigStorage, StorageHolder};
-use skyline_web::{Visibility, Webpage};
+use skyline_web::{Visibility, WebSession, Webpage};
+
+use crate::config::{self, ModFolder};
 
 #[derive(Debug, Deserialize)]
 pub struct ConfigChanged {
@@ -10,10 +12,18 @@ pub struct ConfigChanged {
     value: String,
 }
 
+fn get_mod_folders<CS: ConfigStorage>(storage: &StorageHolder<CS>) -> Vec<ModFolder> {
+    config::resolve_mod_folders(storage.get_field_json("mod_folders").unwrap_or_default())
+}
+
+fn send_mod_folders(session: &WebSession, folders: &[ModFolder]) {
+    session.send(&format!("mod_folders:{}", serde_json::to_string(folders).unwrap()));
+}
+
 // Is this trash? Yes
 // Did I have a choice? No
 pub fn show_config_editor<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
-    let reboot_required = false;
+    let mut reboot_required = false;
 
     let session = std::boxed::Box::new(
         Webpage::new()
@@ -67,6 +77,8 @@ pub fn show_config_editor<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
     let logging: String = storage.get_field("logging_level").unwrap_or(String::from("Info"));
     session.send(&logging);
 
+    send_mod_folders(&session, &get_mod_folders(storage));
+
     while let Ok(msg) = session.recv_json::<ConfigChanged>() {
         match msg.category.as_str() {
             "log" => {
@@ -121,6 +133,31 @@ pub fn show_config_editor<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
                 // info!("Set use_folder_name flag to {}", curr_value);
                 session.send("use_folder_name");
             },
+            // Mod folders are only read during discovery, so changing them requires a reboot
+            "add_mod_folder" => {
+                let mut folders = get_mod_folders(storage);
+                let priority = folders.last().map_or(0, |folder| folder.priority - 1);
+
+                folders.push(ModFolder {
+                    path: msg.value.clone(),
+                    priority,
+                });
+
+                // A folder overlapping with an existing one is dropped here
+                let folders = config::resolve_mod_folders(folders);
+                storage.set_field_json("mod_folders", &folders).unwrap();
+                reboot_required |= folders.iter().any(|folder| folder.path == msg.value.trim().trim_end_matches('/'));
+                send_mod_folders(&session, &folders);
+            },
+            "remove_mod_folder" => {
+                let mut folders = get_mod_folders(storage);
+                let count = folders.len();
+                folders.retain(|folder| folder.path != msg.value);
+
+                storage.set_field_json("mod_folders", &folders).unwrap();
+                reboot_required |= folders.len() != count;
+                send_mod_folders(&session, &get_mod_folders(storage));
+            },
             _ => break,
         }
     }
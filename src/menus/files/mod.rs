@@ -13,6 +13,9 @@ pub static CONFIG_HTML_TEXT: &str = include_str!("../../../resources/templates/c
 pub static CONFIG_CSS_TEXT: &str = include_str!("../../../resources/css/configurator.css");
 pub static CONFIG_JAVASCRIPT_TEXT: &str = include_str!("../../../resources/js/configurator.js");
 
+pub static PLUGIN_SETTINGS_HTML_TEXT: &str = include_str!("../../../resources/templates/plugin_settings.html");
+pub static PLUGIN_SETTINGS_JAVASCRIPT_TEXT: &str = include_str!("../../../resources/js/plugin_settings.js");
+
 pub static CHANGELOG_HTML_TEXT: &str = include_str!("../../../resources/templates/notes.html");
 // Change this for different pictures in the changelog
 pub static CHANGELOG_IMAGE_BYTES: &[u8] = include_bytes!("../../../resources/img/note_thumbnail.png");
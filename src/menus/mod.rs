@@ -10,13 +10,18 @@ pub mod changelog;
 pub use changelog::*;
 pub mod files;
 pub use files::*;
+pub mod plugins;
+pub use plugins::*;
 use skyline_web::Webpage;
 
 pub fn show_main_menu() {
+    // Settings pages registered by plugins are listed after our own pages
+    let menu_html = crate::menus::files::MENU_HTML_TEXT.replace("<!-- PLUGIN_PAGES -->", &menu_buttons());
+
     let response = std::boxed::Box::new(
         Webpage::new()
             .htdocs_dir("contents")
-            .file("index.html", &crate::menus::files::MENU_HTML_TEXT)
+            .file("index.html", &menu_html)
             .file("menu.css", &crate::menus::files::MENU_CSS_TEXT)
             .file("menu.js", &crate::menus::files::MENU_JAVASCRIPT_TEXT)
             .file("common.js", &crate::menus::files::COMMON_JAVASCRIPT_TEXT)
@@ -38,6 +43,9 @@ pub fn show_main_menu() {
             "http://localhost/config" => {
                 show_config_editor(&mut crate::config::GLOBAL_CONFIG.lock().unwrap());
             },
+            url if let Some(index) = url.strip_prefix("http://localhost/plugin/").and_then(|index| index.parse().ok()) => {
+                show_plugin_settings(index);
+            },
             _ => {},
         },
     }
@@ -0,0 +1,205 @@
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use skyline_web::Webpage;
+
+use crate::config;
+
+/// A single option of a plugin settings page
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PluginOptionKind {
+    /// Toggled on and off
+    Flag,
+    /// One value out of a list, the first one being the default
+    Choice { choices: Vec<String> },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginOption {
+    pub key: String,
+    pub label: String,
+    #[serde(flatten)]
+    pub kind: PluginOptionKind,
+}
+
+/// A settings page registered by a plugin. Every option is stored in the plugin's own namespace of the config storage.
+#[derive(Debug, Clone)]
+pub struct PluginPage {
+    pub namespace: String,
+    pub title: String,
+    pub options: Vec<PluginOption>,
+}
+
+static PLUGIN_PAGES: Lazy<RwLock<Vec<PluginPage>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// Namespaces and keys end up as file names in the config storage, so only allow what is safe there
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The name of the field that stores an option of a plugin
+pub fn storage_key(namespace: &str, key: &str) -> String {
+    format!("{}.{}", namespace, key)
+}
+
+/// Adds a settings page to the main menu. Returns false if a page already uses the same namespace.
+pub fn register_page(page: PluginPage) -> bool {
+    let mut pages = PLUGIN_PAGES.write();
+
+    if pages.iter().any(|existing| existing.namespace == page.namespace) {
+        return false;
+    }
+
+    pages.push(page);
+    true
+}
+
+/// Gets the current value of a flag option, which is off by default
+pub fn get_flag(namespace: &str, key: &str) -> bool {
+    config::GLOBAL_CONFIG.lock().unwrap().get_flag(&storage_key(namespace, key))
+}
+
+/// Gets the current value of a choice option, falling back to its first choice
+pub fn get_choice(namespace: &str, key: &str, choices: &[String]) -> String {
+    let value: Option<String> = config::GLOBAL_CONFIG.lock().unwrap().get_field(&storage_key(namespace, key)).ok();
+
+    match value {
+        Some(value) if choices.contains(&value) => value,
+        _ => choices.first().cloned().unwrap_or_default(),
+    }
+}
+
+/// Gets the current value of a choice option of a registered page. Returns None if there is no such option.
+pub fn get_option_choice(namespace: &str, key: &str) -> Option<String> {
+    let choices = PLUGIN_PAGES
+        .read()
+        .iter()
+        .filter(|page| page.namespace == namespace)
+        .flat_map(|page| page.options.iter())
+        .find_map(|option| match &option.kind {
+            PluginOptionKind::Choice { choices } if option.key == key => Some(choices.clone()),
+            _ => None,
+        })?;
+
+    Some(get_choice(namespace, key, &choices))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Builds the main menu buttons of every registered page
+pub fn menu_buttons() -> String {
+    PLUGIN_PAGES
+        .read()
+        .iter()
+        .enumerate()
+        .map(|(index, page)| {
+            format!(
+                r#"<button onclick="location.href = 'http://localhost/plugin/{}'" class="flex-item">
+                        <div class="icon-background"></div>
+                        <div class="item-container">
+                            <h2>{}</h2>
+                        </div>
+                    </button>"#,
+                index,
+                escape_html(&page.title)
+            )
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+struct OptionInformation {
+    #[serde(flatten)]
+    option: PluginOption,
+    value: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Information {
+    title: String,
+    options: Vec<OptionInformation>,
+}
+
+#[derive(Debug, Deserialize)]
+pub enum PluginSettingsMessage {
+    Set { key: String, value: String },
+    ClosureRequest,
+}
+
+/// Renders the settings page of a plugin through the generic settings template
+pub fn show_plugin_settings(index: usize) {
+    let page = match PLUGIN_PAGES.read().get(index) {
+        Some(page) => page.clone(),
+        None => return,
+    };
+
+    let info = Information {
+        title: page.title.clone(),
+        options: page
+            .options
+            .iter()
+            .map(|option| {
+                let value = match &option.kind {
+                    PluginOptionKind::Flag => get_flag(&page.namespace, &option.key).to_string(),
+                    PluginOptionKind::Choice { choices } => get_choice(&page.namespace, &option.key, choices),
+                };
+
+                OptionInformation {
+                    option: option.clone(),
+                    value,
+                }
+            })
+            .collect(),
+    };
+
+    let session = Webpage::new()
+        .htdocs_dir("contents")
+        .file("index.html", &crate::menus::files::PLUGIN_SETTINGS_HTML_TEXT)
+        .file("configurator.css", &crate::menus::files::CONFIG_CSS_TEXT)
+        .file("plugin_settings.js", &crate::menus::files::PLUGIN_SETTINGS_JAVASCRIPT_TEXT)
+        .file("check.svg", &crate::menus::files::CHECK_SVG)
+        .file("common.js", &crate::menus::files::COMMON_JAVASCRIPT_TEXT)
+        .file("plugin_settings.json", &serde_json::to_string(&info).unwrap())
+        .background(skyline_web::Background::Default)
+        .boot_display(skyline_web::BootDisplay::Default)
+        .open_session(skyline_web::Visibility::Default)
+        .unwrap();
+
+    while let Ok(message) = session.recv_json::<PluginSettingsMessage>() {
+        match message {
+            PluginSettingsMessage::Set { key, value } => {
+                let option = match page.options.iter().find(|option| option.key == key) {
+                    Some(option) => option,
+                    None => continue,
+                };
+
+                let mut storage = config::GLOBAL_CONFIG.lock().unwrap();
+                let field = storage_key(&page.namespace, &key);
+
+                let result = match &option.kind {
+                    PluginOptionKind::Flag => storage.set_flag(&field, value == "true"),
+                    PluginOptionKind::Choice { choices } if choices.contains(&value) => storage.set_field(&field, &value),
+                    PluginOptionKind::Choice { .. } => continue,
+                };
+
+                if let Err(e) = result {
+                    error!("Failed to set option '{}' of plugin settings '{}'. Reason: {:?}", key, page.namespace, e);
+                }
+            },
+            PluginSettingsMessage::ClosureRequest => {
+                session.exit();
+                session.wait_for_exit();
+                break;
+            },
+        }
+    }
+
+    config::GLOBAL_CONFIG.lock().unwrap().flush();
+}
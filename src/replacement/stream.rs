@@ -10,8 +10,8 @@ fn lookup_stream_hash(out_path: *mut c_char, loaded_arc: &LoadedArc, size_out: &
         // restrictions by the stream API require us to be able to load this file via std::fs
         // therefore, it is fair to use the StandardLoader to query both its existence and the filesize
         if let Some(path) = fs.hash(hash) {
-            // at this point if it is a patch file this should pass, if it's a callback file
-            // this should fail
+            // at this point if it is a patch file or a data callback file (served through apistream:/) this should pass,
+            // if it's a path callback file this should fail
             // if it is a callback file, it has to return a valid path that the system can read so we can just
             // stat it
             if let Some(size) = fs.get().query_max_filesize(local_path) {
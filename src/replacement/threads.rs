@@ -4,7 +4,7 @@ use smash_arc::{ArcLookup, Hash40};
 
 use super::FileInfoFlagsExt;
 use crate::{
-    config, hashes, offsets, reg_w, reg_x,
+    api, config, hashes, offsets, reg_w, reg_x,
     resource::{self, InflateFile, LoadInfo, LoadType},
     GLOBAL_FILESYSTEM,
 };
@@ -35,6 +35,9 @@ fn inflate_incoming(ctx: &InlineCtx) {
     let should_add = if let Some(path) = fs.hash(path_hash) {
         info!("Added file '{}' to the queue.", path.display().yellow());
         true
+    } else if api::has_extension_callbacks(file_path.ext.hash40()) {
+        info!("Added file '{}' to the queue for its extension callbacks.", hashes::find(path_hash).yellow());
+        true
     } else {
         false
     };
@@ -114,19 +117,32 @@ pub fn handle_file_replace(hash: Hash40) {
         )
     };
 
-    if let Some(size) = fs.load_into(hash, buffer) {
-        if arc.get_file_paths()[filepath_index].ext.hash40() == Hash40::from("nutexb") {
+    // Files that are not modded are only here for their extension callbacks, and the game already put their data in the buffer
+    let size = if fs.local_hash(hash).is_some() { fs.load_into(hash, buffer) } else { Some(buffer.len()) };
+
+    if let Some(size) = size {
+        let bank_id = if file_info.flags.unshared_nus3bank() { fs.get_bank_id(hash) } else { None };
+
+        if let Some(source) = fs.get_file_source(hash) {
+            debug!("File '{}' ({:#x}) comes from {}", hashes::find(hash), hash.0, source);
+        }
+
+        // Extension callbacks are plugin code which can call into the API, and the filesystem lock is not reentrant
+        drop(fs);
+
+        let ext = arc.get_file_paths()[filepath_index].ext.hash40();
+        let size = api::run_extension_callbacks(hash, ext, buffer, size);
+
+        if ext == Hash40::from("nutexb") {
             if size < decompressed_size as usize {
                 let (contents, footer) = buffer.split_at_mut((decompressed_size - 0xb0) as usize);
                 footer.copy_from_slice(&contents[(size - 0xb0)..size]);
             }
-        } else if file_info.flags.unshared_nus3bank() {
+        } else if let Some(id) = bank_id {
             static GRP_BYTES: &[u8] = &[0x47, 0x52, 0x50, 0x20];
-            if let Some(id) = fs.get_bank_id(hash) {
-                let buffer = &mut buffer[0x30..];
-                if let Some(offset) = buffer.windows(GRP_BYTES.len()).position(|window| window == GRP_BYTES) {
-                    buffer[(offset - 4)..offset].copy_from_slice(&id.to_le_bytes());
-                }
+            let buffer = &mut buffer[0x30..];
+            if let Some(offset) = buffer.windows(GRP_BYTES.len()).position(|window| window == GRP_BYTES) {
+                buffer[(offset - 4)..offset].copy_from_slice(&id.to_le_bytes());
             }
         }
         info!(
@@ -137,6 +153,8 @@ pub fn handle_file_replace(hash: Hash40) {
             size,
             resource::res_service().buffer_size
         );
+
+        api::event::send_event_v2(api::event::QueuedEvent::file_replaced(hash, size));
     } else {
         warn!(
             "Failed to load file '{}' ({:#x}) into buffer with size {:#X}",
@@ -166,6 +184,8 @@ fn res_loop_common() {
     let file_infos = arc.get_file_infos();
     let dir_infos = arc.get_dir_infos();
 
+    negotiate_sizes();
+
     let mut standalone_files = vec![Vec::new(); 5];
 
     for (list_idx, list) in service.res_lists.iter().enumerate() {
@@ -197,6 +217,35 @@ fn res_loop_common() {
     }
 }
 
+/// Asks plugins for the exact size of the queued files they provide, before the game allocates their buffers
+fn negotiate_sizes() {
+    // This runs on every iteration of the res loop, so don't block the other threads unless a plugin registered a size callback
+    if !GLOBAL_FILESYSTEM.read().has_size_callbacks() {
+        return;
+    }
+
+    let mut fs = GLOBAL_FILESYSTEM.write();
+
+    let arc = resource::arc();
+    let service = resource::res_service();
+    let file_paths = arc.get_file_paths();
+    let file_infos = arc.get_file_infos();
+    let dir_infos = arc.get_dir_infos();
+
+    for list in service.res_lists.iter() {
+        for entry in list.iter() {
+            match entry.ty {
+                LoadType::File => fs.negotiate_size(file_paths[entry.filepath_index as usize].path.hash40()),
+                LoadType::Directory => {
+                    for info in file_infos[dir_infos[entry.directory_index as usize].file_info_range()].iter() {
+                        fs.negotiate_size(file_paths[info.file_path_index].path.hash40());
+                    }
+                },
+            }
+        }
+    }
+}
+
 pub fn install() {
     skyline::install_hooks!(inflate_incoming, inflate_dir_file, res_loop_start, res_loop_refresh);
 }
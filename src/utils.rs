@@ -53,20 +53,94 @@ pub mod env {
 pub mod paths {
     use super::env::get_game_version;
     use camino::Utf8PathBuf;
-    use std::io;
+    use std::{
+        io,
+        path::{Path, PathBuf},
+    };
+
+    /// The file that marks a folder of a mod folder as a collection of mods rather than a mod
+    pub static COLLECTION_MANIFEST: &str = "collection.toml";
 
     pub fn ensure_paths_exist() -> io::Result<()> {
-        std::fs::create_dir_all(mods())?;
         std::fs::create_dir_all(config())?;
         std::fs::create_dir_all(logs())?;
         std::fs::create_dir_all(cache())?;
+
         Ok(())
     }
 
+    /// Creates the configured mod folders. Called once the logger is up, since they come from the configuration.
+    pub fn ensure_mod_folders_exist() {
+        // A misconfigured mod folder should not prevent the game from booting, its mods just won't be found
+        for folder in mod_folders() {
+            if let Err(e) = std::fs::create_dir_all(&folder) {
+                warn!("Failed to create mod folder '{}'. Reason: {:?}", folder, e);
+            }
+        }
+    }
+
+    /// The default mod folder, used when none are configured
     pub fn mods() -> Utf8PathBuf {
         Utf8PathBuf::from("sd:/ultimate/mods")
     }
 
+    /// The folders that mods are discovered in, from the highest priority to the lowest
+    pub fn mod_folders() -> Vec<Utf8PathBuf> {
+        crate::config::mod_folders()
+            .into_iter()
+            .map(|folder| Utf8PathBuf::from(folder.path))
+            .collect()
+    }
+
+    /// Gets the mod folder that a mod is in, out of the given mod folders. The deepest one wins if several contain the mod.
+    pub fn mod_folder_of<'a>(mod_path: &Path, folders: &'a [Utf8PathBuf]) -> Option<&'a Utf8PathBuf> {
+        folders
+            .iter()
+            .filter(|folder| mod_path.starts_with(folder))
+            .max_by_key(|folder| folder.components().count())
+    }
+
+    /// Lists every mod of every mod folder, including the ones inside of collections. Collections are never mods themselves.
+    pub fn mod_roots() -> Vec<PathBuf> {
+        fn visit(folder: &Path, roots: &mut Vec<PathBuf>) {
+            let entries = match std::fs::read_dir(folder) {
+                Ok(entries) => entries,
+                Err(_) => return,
+            };
+
+            for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+                if !path.is_dir() {
+                    continue;
+                }
+
+                if path.join(COLLECTION_MANIFEST).exists() {
+                    visit(&path, roots);
+                } else {
+                    roots.push(path);
+                }
+            }
+        }
+
+        let mut roots = Vec::new();
+
+        for folder in mod_folders() {
+            visit(folder.as_std_path(), &mut roots);
+        }
+
+        roots
+    }
+
+    /// Gets the collection a mod is in, relative to its mod folder, or None if it is directly in the mod folder
+    pub fn collection_of(mod_path: &Path, folders: &[Utf8PathBuf]) -> Option<PathBuf> {
+        let folder = mod_folder_of(mod_path, folders)?;
+
+        mod_path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(folder.as_std_path()).ok())
+            .filter(|collection| !collection.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+    }
+
     pub fn config() -> Utf8PathBuf {
         Utf8PathBuf::from("sd:/ultimate/arcropolis/config")
     }